@@ -17,5 +17,14 @@ else
 fi
 
 
-qemu-system-i386 $gdb $graphics -kernel $1 -device rtl8139,bus=pci.0,addr=4,mac=69:69:69:69:69:69
+# The tests exit QEMU through isa-debug-exit, with 33 when they all passed
+qemu-system-i386 $gdb $graphics -kernel $1 -device rtl8139,bus=pci.0,addr=4,mac=69:69:69:69:69:69 \
+	-device isa-debug-exit,iobase=0xf4,iosize=0x04
+status=$?
+
+if [ $status == 33 ]
+then
+	exit 0
+fi
+exit $status
 
@@ -0,0 +1,224 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+
+use crate::memory::paging::{self, PageFlags};
+use crate::memory::phys_to_virt;
+
+// https://wiki.osdev.org/RSDP
+// https://wiki.osdev.org/RSDT
+// https://wiki.osdev.org/MADT
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+const EBDA_SEGMENT_POINTER: usize = 0x40E;
+const BIOS_AREA_START: usize = 0xE0000;
+const BIOS_AREA_END: usize = 0x100000;
+
+// The copy of the RSDP the bootloader gave, if it did
+static mut BOOT_RSDP: Option<*const Rsdp> = None;
+
+#[allow(unused)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+}
+
+#[allow(unused)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+unsafe fn checksum_ok(ptr: *const u8, len: usize) -> bool {
+    let mut sum = 0u8;
+    for i in 0..len {
+        sum = sum.wrapping_add(*ptr.add(i));
+    }
+    sum == 0
+}
+
+unsafe fn scan_for_rsdp(start: usize, end: usize) -> Option<*const Rsdp> {
+    // The RSDP is always on a 16 bytes boundary
+    for addr in (phys_to_virt(start)..phys_to_virt(end)).step_by(16) {
+        let candidate = addr as *const [u8; 8];
+        if read_unaligned(candidate) == *RSDP_SIGNATURE
+            && checksum_ok(addr as *const u8, size_of::<Rsdp>())
+        {
+            return Some(addr as *const Rsdp);
+        }
+    }
+    None
+}
+
+// The tables are in memory the firmware reserved, which may not be mapped with the rest of RAM.
+// Returns where the table at the physical address `phys` is mapped, and its length.
+unsafe fn map_table(phys: usize) -> Option<(*const SdtHeader, usize)> {
+    let header = paging::map_physical(phys, size_of::<SdtHeader>(), PageFlags::PRESENT).ok()?;
+    let length = read_unaligned(core::ptr::addr_of!((*(header as *const SdtHeader)).length));
+    let table = paging::map_physical(phys, length as usize, PageFlags::PRESENT).ok()?;
+    Some((table as *const SdtHeader, length as usize))
+}
+
+/// Use the RSDP the bootloader found instead of looking for it in the BIOS areas, which are
+/// missing when booted from UEFI.
+pub fn set_rsdp(rsdp: &'static [u8]) {
+    unsafe {
+        if rsdp.len() >= size_of::<Rsdp>()
+            && rsdp.starts_with(RSDP_SIGNATURE)
+            && checksum_ok(rsdp.as_ptr(), size_of::<Rsdp>())
+        {
+            *core::ptr::addr_of_mut!(BOOT_RSDP) = Some(rsdp.as_ptr() as *const Rsdp);
+        }
+    }
+}
+
+unsafe fn find_rsdp() -> Option<*const Rsdp> {
+    if let Some(rsdp) = *core::ptr::addr_of!(BOOT_RSDP) {
+        return Some(rsdp);
+    }
+
+    // Either in the first KiB of the Extended BIOS Data Area, or in the BIOS read only area.
+    let ebda = (read_unaligned(phys_to_virt(EBDA_SEGMENT_POINTER) as *const u16) as usize) << 4;
+    if ebda != 0 {
+        if let Some(rsdp) = scan_for_rsdp(ebda, ebda + 1024) {
+            return Some(rsdp);
+        }
+    }
+    scan_for_rsdp(BIOS_AREA_START, BIOS_AREA_END)
+}
+
+/// Look for the ACPI table with the given signature through the RSDT.
+pub fn find_table(signature: &[u8; 4]) -> Option<*const SdtHeader> {
+    unsafe {
+        let rsdp = find_rsdp()?;
+        let rsdt_address = read_unaligned(core::ptr::addr_of!((*rsdp).rsdt_address));
+        let (rsdt, rsdt_length) = map_table(rsdt_address as usize)?;
+        if !checksum_ok(rsdt as *const u8, rsdt_length) {
+            return None;
+        }
+
+        let entries = (rsdt_length - size_of::<SdtHeader>()) / 4;
+        let first_entry = (rsdt as *const u8).add(size_of::<SdtHeader>()) as *const u32;
+        for i in 0..entries {
+            let (table, length) = map_table(read_unaligned(first_entry.add(i)) as usize)?;
+            let header = read_unaligned(table);
+            if header.signature == *signature && checksum_ok(table as *const u8, length) {
+                return Some(table);
+            }
+        }
+        None
+    }
+}
+
+#[allow(unused)]
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApicEntry {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[allow(unused)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ that is not wired to the I/O APIC pin of the same number, or not with the ISA
+/// polarity and trigger mode.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptSourceOverride {
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+#[allow(unused)]
+#[derive(Debug)]
+pub struct Madt {
+    pub local_apic_address: u32,
+    pub has_legacy_pics: bool,
+    pub processors: Vec<LocalApicEntry>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub overrides: Vec<InterruptSourceOverride>,
+}
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// Find and decode the Multiple APIC Description Table.
+pub fn parse_madt() -> Option<Madt> {
+    let table = find_table(MADT_SIGNATURE)?;
+
+    unsafe {
+        let length = read_unaligned(core::ptr::addr_of!((*table).length)) as usize;
+        let base = table as *const u8;
+        let body = base.add(size_of::<SdtHeader>());
+
+        let mut madt = Madt {
+            local_apic_address: read_unaligned(body as *const u32),
+            has_legacy_pics: read_unaligned(body.add(4) as *const u32) & 0x1 != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+        };
+
+        // Variable length entries, each starting with its type and its length
+        let mut offset = size_of::<SdtHeader>() + 8;
+        while offset + 2 <= length {
+            let entry = base.add(offset);
+            let entry_type = *entry;
+            let entry_length = *entry.add(1) as usize;
+            if entry_length < 2 {
+                break;
+            }
+
+            match entry_type {
+                MADT_LOCAL_APIC => madt.processors.push(LocalApicEntry {
+                    processor_id: *entry.add(2),
+                    apic_id: *entry.add(3),
+                    enabled: read_unaligned(entry.add(4) as *const u32) & 0x1 != 0,
+                }),
+                MADT_IO_APIC => madt.io_apics.push(IoApicEntry {
+                    id: *entry.add(2),
+                    address: read_unaligned(entry.add(4) as *const u32),
+                    gsi_base: read_unaligned(entry.add(8) as *const u32),
+                }),
+                MADT_INTERRUPT_SOURCE_OVERRIDE => madt.overrides.push(InterruptSourceOverride {
+                    source: *entry.add(3),
+                    gsi: read_unaligned(entry.add(4) as *const u32),
+                    flags: read_unaligned(entry.add(8) as *const u16),
+                }),
+                MADT_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                    // 64-bit address, we can only use it if it is below 4 GiB
+                    let address = read_unaligned(entry.add(4) as *const u64);
+                    if let Ok(address) = u32::try_from(address) {
+                        madt.local_apic_address = address;
+                    }
+                }
+                _ => {}
+            }
+
+            offset += entry_length;
+        }
+
+        Some(madt)
+    }
+}
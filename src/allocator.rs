@@ -1,188 +1,884 @@
-use crate::boot::MultibootInfo;
-use core::{alloc::GlobalAlloc, mem::size_of};
+use crate::memory::frame::{self, FRAME_SIZE};
+use crate::memory::phys_to_virt;
+use crate::sync::{IrqSafeMutex, IrqSafeMutexGuard};
+use alloc::collections::TryReserveError;
+use alloc::vec::Vec;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    mem::size_of,
+};
 
 #[global_allocator]
-pub static mut ALLOCATOR: Allocator = Allocator::new();
+pub static ALLOCATOR: Allocator = Allocator::new();
+
+// How much memory the heap takes from the frame allocator at first, and at least how much more
+// each time it runs out.
+const HEAP_GROWTH: usize = 1024 * 1024;
 
 pub struct Allocator {
-    mmap_addr: *mut u8,
-    mmap_size: usize,
+    heap: IrqSafeMutex<Heap>,
 }
 
 impl Allocator {
     const fn new() -> Self {
         Self {
-            mmap_addr: 0 as *mut u8,
-            mmap_size: 0,
+            heap: IrqSafeMutex::new(Heap {
+                first_region: core::ptr::null_mut(),
+                free_lists: [core::ptr::null_mut(); SIZE_CLASSES],
+                #[cfg(feature = "heap-bench")]
+                policy: FitPolicy::SegregatedFit,
+                size: 0,
+                in_use: 0,
+                peak_in_use: 0,
+                allocations: 0,
+            }),
+        }
+    }
+
+    /// Take the first memory of the heap from the frame allocator, which must be initialized.
+    pub unsafe fn init(&self) {
+        if !self.heap.lock().grow(HEAP_GROWTH) {
+            panic!("No memory for the heap");
+        }
+    }
+
+    /// Every block of the heap, region after region. The heap stays locked until the iterator is
+    /// dropped, nothing can be allocated meanwhile.
+    pub fn blocks(&self) -> Blocks<'_> {
+        let heap = self.heap.lock();
+        Blocks {
+            region: heap.first_region,
+            next: None,
+            heap,
+        }
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let blocks = self.blocks();
+        let mut stats = HeapStats {
+            size: blocks.heap.size,
+            bytes_in_use: blocks.heap.in_use,
+            peak_bytes_in_use: blocks.heap.peak_in_use,
+            live_allocations: blocks.heap.allocations,
+            free_bytes: 0,
+            free_blocks: 0,
+            largest_free_block: 0,
+        };
+        for block in blocks.filter(|block| !block.allocated) {
+            stats.free_bytes += block.size;
+            stats.free_blocks += 1;
+            stats.largest_free_block = stats.largest_free_block.max(block.size);
+        }
+        stats
+    }
+}
+
+#[cfg(feature = "heap-bench")]
+impl Allocator {
+    /// A heap of its own on the memory from `start` to `end`, looking for free blocks with
+    /// `policy`, to compare the policies. The memory must not be used by anything else. Like the
+    /// kernel heap it grows from the frame allocator when it runs out, and never gives that back.
+    pub unsafe fn with_region(policy: FitPolicy, start: usize, end: usize) -> Self {
+        let allocator = Self::new();
+        {
+            let mut heap = allocator.heap.lock();
+            heap.policy = policy;
+            heap.add_region(start, end);
         }
+        allocator
     }
+}
 
-    pub unsafe fn init(&mut self, multiboot_infos: &'static MultibootInfo) {
-        let mmap = multiboot_infos
-            .get_mmap_addrs()
-            .iter()
-            .find(|e| e.addr() == (&crate::KERNEL_START as *const u32) as u64)
-            .expect("There is at least one available memory map");
+/// How `alloc` looks for a free block.
+#[cfg(feature = "heap-bench")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitPolicy {
+    /// Walk every block of every region and take the first free one that fits, so the cost
+    /// grows with the number of blocks in the heap. Only the search is the one from before the
+    /// free lists: they are still kept up to date, which `alloc` and `dealloc` pay for.
+    FirstFit,
+    /// Look through the free blocks of the size class of the allocation, then take the first
+    /// one of a larger class
+    SegregatedFit,
+}
 
-        let kernel_start_addr = (&crate::KERNEL_START as *const u32) as u64;
-        let kernel_end_addr = (&crate::KERNEL_END as *const u32) as u64;
+struct Heap {
+    first_region: *mut Region,
+    // The free blocks, by size class
+    free_lists: [*mut Header; SIZE_CLASSES],
+    #[cfg(feature = "heap-bench")]
+    policy: FitPolicy,
+    // Bytes of all the regions, headers included
+    size: usize,
+    // Bytes of the allocated blocks, without their headers
+    in_use: usize,
+    peak_in_use: usize,
+    allocations: usize,
+}
 
-        let reserved_memory_length = kernel_end_addr - kernel_start_addr;
+// The regions are only reached through the lock of the allocator
+unsafe impl Send for Heap {}
 
-        let reallign = (mmap.len() - reserved_memory_length) % 0x4;
-        let reserved_memory_length = reserved_memory_length + reallign;
+impl Heap {
+    /// Take at least `size` more bytes from the frame allocator, returns false if there is no
+    /// physical memory left.
+    unsafe fn grow(&mut self, size: usize) -> bool {
+        // Try to take more than asked, so small allocations don't each need new frames
+        for size in [size.max(HEAP_GROWTH), size] {
+            let count = align_up(size, FRAME_SIZE) / FRAME_SIZE;
+            if let Some(first) = frame::allocate_contiguous(count) {
+                let start = phys_to_virt(first.start_address());
+                self.add_region(start, start + count * FRAME_SIZE);
+                return true;
+            }
+        }
+        false
+    }
 
-        self.mmap_size = (mmap.len() - reserved_memory_length) as usize;
-        self.mmap_addr = (kernel_start_addr + reserved_memory_length) as *mut u8;
+    /// Give the memory from `start` to `end` to the heap. It must not be used by anything else.
+    unsafe fn add_region(&mut self, start: usize, end: usize) {
+        let start = align_up(start, GRANULARITY);
+        let end = align_down(end, GRANULARITY);
+        if end <= start || end - start < REGION_HEADER_SIZE + 2 * HEADER_SIZE + MIN_BLOCK_SIZE {
+            return;
+        }
 
-        let a = self.mmap_addr;
-        let header = Header {
-            special: true,
-            alloced: false,
-            size: self.mmap_size - size_of::<Header>() - size_of::<Header>(),
-        };
+        self.size += end - start;
+        let region = start as *mut Region;
+        region.write(Region {
+            next: core::ptr::null_mut(),
+            size: end - start,
+        });
+        write_block(
+            first_block(region),
+            end - start - REGION_HEADER_SIZE - 2 * HEADER_SIZE,
+            false,
+            true,
+            true,
+        );
+        self.push_free(first_block(region));
+
+        // Keep the regions in the order they were given
+        if self.first_region.is_null() {
+            self.first_region = region;
+            return;
+        }
+        let mut last = self.first_region;
+        while !(*last).next.is_null() {
+            last = (*last).next;
+        }
+        (*last).next = region;
+    }
+
+    /// Panic if freeing or resizing the allocation of `size` bytes at `block` isn't right: it
+    /// isn't a block of the heap, it is already free, or something wrote past its end.
+    #[cfg(feature = "heap-debug")]
+    unsafe fn check_free(&self, block: *mut Header, size: usize) {
+        let address = block as usize;
+        let mut region = self.first_region;
+        while !region.is_null()
+            && !(region as usize..region as usize + (*region).size).contains(&address)
+        {
+            region = (*region).next;
+        }
+        if region.is_null() {
+            panic!(
+                "Heap corruption: invalid free of {:#010x} ({size} bytes), not in the heap",
+                payload(block) as usize
+            );
+        }
+        if (*block).magic != magic((*block).size) {
+            // A block that was merged with the one before it when freed is poisoned
+            let header = core::slice::from_raw_parts(block.cast::<u8>(), HEADER_SIZE);
+            let what = if header.iter().all(|&byte| byte == POISON) {
+                "double free"
+            } else {
+                "invalid free"
+            };
+            panic!(
+                "Heap corruption: {what} of {:#010x} ({size} bytes), not the start of a block",
+                payload(block) as usize
+            );
+        }
+        if !(*block).alloced {
+            corruption(block, "double free");
+        }
+        check_block(block);
+        // Freeing looks at the previous block through its footer
+        if !(*block).special {
+            let previous_footer = block.cast::<u8>().sub(HEADER_SIZE).cast::<Header>();
+            if (*previous_footer).magic != magic((*previous_footer).size) {
+                corruption(block, "overwritten previous footer");
+            }
+        }
+        let trailer = core::slice::from_raw_parts(payload(block).add(size), (*block).size - size);
+        if trailer.iter().any(|&byte| byte != TRAILER_CANARY) {
+            corruption(block, "overwritten trailer");
+        }
+    }
+
+    #[cfg(not(feature = "heap-debug"))]
+    unsafe fn check_free(&self, _block: *mut Header, _size: usize) {}
+
+    /// Add the free `block` to the list of its size class.
+    unsafe fn push_free(&mut self, block: *mut Header) {
+        let class = size_class((*block).size);
+        let head = self.free_lists[class];
+        links(block).write(FreeLinks {
+            previous: core::ptr::null_mut(),
+            next: head,
+        });
+        if !head.is_null() {
+            (*links(head)).previous = block;
+        }
+        self.free_lists[class] = block;
+    }
+
+    /// Take the free `block` out of the list of its size class.
+    unsafe fn remove_free(&mut self, block: *mut Header) {
+        let FreeLinks { previous, next } = links(block).read();
+        if previous.is_null() {
+            self.free_lists[size_class((*block).size)] = next;
+        } else {
+            (*links(previous)).next = next;
+        }
+        if !next.is_null() {
+            (*links(next)).previous = previous;
+        }
+    }
+
+    /// A free block that can hold `size` bytes aligned on `align`, still in its free list.
+    unsafe fn find_fit(&self, size: usize, align: usize) -> *mut Header {
+        #[cfg(feature = "heap-bench")]
+        if self.policy == FitPolicy::FirstFit {
+            return self.find_first_fit(size, align);
+        }
+        self.find_segregated_fit(size, align)
+    }
+
+    /// First free block, in any region, that can hold `size` bytes aligned on `align`.
+    #[cfg(feature = "heap-bench")]
+    unsafe fn find_first_fit(&self, size: usize, align: usize) -> *mut Header {
+        let mut region = self.first_region;
+        while !region.is_null() {
+            let mut block = Some(first_block(region));
+            while let Some(current) = block {
+                check_block(current);
+                if !(*current).alloced && fits(current, size, align) {
+                    return current;
+                }
+                block = next_block(current);
+            }
+            region = (*region).next;
+        }
+        core::ptr::null_mut()
+    }
+
+    /// A free block that can hold `size` bytes aligned on `align`, from the smallest size class
+    /// that may have one. Only the first class can have blocks too small, so unless alignment
+    /// gets in the way the search stops at the first block of the classes above it.
+    unsafe fn find_segregated_fit(&self, size: usize, align: usize) -> *mut Header {
+        for class in size_class(size)..SIZE_CLASSES {
+            let mut block = self.free_lists[class];
+            while !block.is_null() {
+                check_block(block);
+                if fits(block, size, align) {
+                    return block;
+                }
+                block = (*links(block)).next;
+            }
+        }
+        core::ptr::null_mut()
+    }
+
+    /// Give the free `block` back to the free lists, merged with the free blocks around it.
+    unsafe fn release(&mut self, block: *mut Header) {
+        // Merge with the following block
+        if let Some(next) = next_block(block) {
+            check_block(next);
+            if !(*next).alloced {
+                self.remove_free(next);
+                let last = (*footer(next)).special;
+                let size = (*block).size + 2 * HEADER_SIZE + (*next).size;
+                write_block(block, size, false, (*block).special, last);
+            }
+        }
 
-        a.copy_from_nonoverlapping((&header as *const Header) as *const u8, size_of::<Header>());
-        let a = a.add(self.mmap_size - size_of::<Header>());
-        a.copy_from_nonoverlapping((&header as *const Header) as *const u8, size_of::<Header>());
+        // And with the previous one
+        let mut block = block;
+        if let Some(previous) = previous_block(block) {
+            check_block(previous);
+            if !(*previous).alloced {
+                self.remove_free(previous);
+                let last = (*footer(block)).special;
+                let size = (*previous).size + 2 * HEADER_SIZE + (*block).size;
+                write_block(previous, size, false, (*previous).special, last);
+                block = previous;
+            }
+        }
+        poison(block);
+        self.push_free(block);
     }
+
+    /// Make the allocated `block` `size` bytes large without moving it, taking the following
+    /// block if it is free and giving back what isn't needed. Returns false if it can't grow.
+    unsafe fn resize(&mut self, block: *mut Header, size: usize) -> bool {
+        if (*block).size < size {
+            let Some(next) = next_block(block) else {
+                return false;
+            };
+            check_block(next);
+            let total = (*block).size + 2 * HEADER_SIZE + (*next).size;
+            if (*next).alloced || total < size {
+                return false;
+            }
+            self.remove_free(next);
+            let last = (*footer(next)).special;
+            self.in_use += total - (*block).size;
+            write_block(block, total, true, (*block).special, last);
+        }
+
+        if (*block).size >= size + 2 * HEADER_SIZE + MIN_BLOCK_SIZE {
+            self.in_use -= (*block).size - size;
+            let rest = split(block, size);
+            (*block).alloced = true;
+            (*footer(block)).alloced = true;
+            self.release(rest);
+        }
+        self.peak_in_use = self.peak_in_use.max(self.in_use);
+        true
+    }
+}
+
+/// A block of the heap, as seen by `Allocator::blocks`.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct BlockInfo {
+    /// Where the payload of the block starts
+    pub address: usize,
+    pub size: usize,
+    pub allocated: bool,
+}
+
+pub struct Blocks<'a> {
+    heap: IrqSafeMutexGuard<'a, Heap>,
+    // Next region to walk once the blocks of the current one are done
+    region: *mut Region,
+    next: Option<*mut Header>,
+}
+
+impl Iterator for Blocks<'_> {
+    type Item = BlockInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            loop {
+                if let Some(block) = self.next {
+                    self.next = next_block(block);
+                    return Some(BlockInfo {
+                        address: payload(block) as usize,
+                        size: (*block).size,
+                        allocated: (*block).alloced,
+                    });
+                }
+                if self.region.is_null() {
+                    return None;
+                }
+                self.next = Some(first_block(self.region));
+                self.region = (*self.region).next;
+            }
+        }
+    }
+}
+
+/// What the heap is made of at some point.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// Memory taken from the frame allocator, headers included
+    pub size: usize,
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub live_allocations: usize,
+    pub free_bytes: usize,
+    pub free_blocks: usize,
+    pub largest_free_block: usize,
+}
+
+impl HeapStats {
+    /// Percentage of the free memory that is outside of the largest free block: how likely a
+    /// large allocation is to fail even though there is enough free memory in total.
+    pub fn fragmentation(&self) -> usize {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+        100 - self.largest_free_block * 100 / self.free_bytes
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "heap_size {}", self.size)?;
+        writeln!(f, "bytes_in_use {}", self.bytes_in_use)?;
+        writeln!(f, "peak_bytes_in_use {}", self.peak_bytes_in_use)?;
+        writeln!(f, "live_allocations {}", self.live_allocations)?;
+        writeln!(f, "free_bytes {}", self.free_bytes)?;
+        writeln!(f, "free_blocks {}", self.free_blocks)?;
+        writeln!(f, "largest_free_block {}", self.largest_free_block)?;
+        writeln!(f, "fragmentation_percent {}", self.fragmentation())
+    }
+}
+
+/// Called when an allocation that can't fail does, like growing a `Vec` with `push`. Code that
+/// can do without the memory uses fallible allocations instead, like `try_with_capacity`.
+#[alloc_error_handler]
+fn out_of_memory(layout: Layout) -> ! {
+    emergency_println!("Heap when it ran out:");
+    emergency_print!("{}", ALLOCATOR.stats());
+    panic!(
+        "Out of memory allocating {} bytes aligned on {}",
+        layout.size(),
+        layout.align()
+    )
+}
+
+/// An empty vector with room for `capacity` elements, or an error if the heap can't give it.
+pub fn try_with_capacity<T>(capacity: usize) -> Result<Vec<T>, TryReserveError> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(capacity)?;
+    Ok(vec)
+}
+
+// Every region of memory given to the heap starts with this, followed by its blocks. Blocks
+// never span two regions: the first header and the last footer of a region are special.
+#[repr(C)]
+struct Region {
+    next: *mut Region,
+    size: usize,
+}
+
+const REGION_HEADER_SIZE: usize = align_up(size_of::<Region>(), GRANULARITY);
+
+unsafe fn first_block(region: *mut Region) -> *mut Header {
+    region.cast::<u8>().add(REGION_HEADER_SIZE).cast::<Header>()
 }
 
-//#[repr(packed)]
 #[derive(Debug)]
+#[repr(C)]
+// The magic makes the header larger, it must stay a power of two
+#[cfg_attr(feature = "heap-debug", repr(align(16)))]
 struct Header {
+    size: usize,
+    // On a header: first block of a region. On a footer: last block of a region.
     special: bool,
     alloced: bool,
-    size: usize,
+    #[cfg(feature = "heap-debug")]
+    magic: u32,
 }
 
 // [Header => alloced: <bool>, size: <u32>]
 // [Allocated item]
 // [Header => alloced: <bool>, size: <u32>]
 
+const HEADER_SIZE: usize = size_of::<Header>();
+
+// A free block keeps the previous and next blocks of its free list at the start of its payload
+#[repr(C)]
+struct FreeLinks {
+    previous: *mut Header,
+    next: *mut Header,
+}
+
+unsafe fn links(block: *mut Header) -> *mut FreeLinks {
+    payload(block).cast::<FreeLinks>()
+}
+
+// Size class `n` holds the free blocks of 2^n to 2^(n+1) - 1 bytes
+const SIZE_CLASSES: usize = usize::BITS as usize;
+
+fn size_class(size: usize) -> usize {
+    (usize::BITS - 1 - size.leading_zeros()) as usize
+}
+
+// Every block size is a multiple of the granularity, and the heap starts on a multiple of it,
+// so every header is aligned and every allocation is at least aligned on it.
+const GRANULARITY: usize = HEADER_SIZE;
+
+// Every block must be able to hold the free list links once it is freed. Blocks are not split if
+// what is left would be smaller.
+const MIN_BLOCK_SIZE: usize = align_up(size_of::<FreeLinks>(), GRANULARITY);
+
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+const fn align_down(addr: usize, align: usize) -> usize {
+    addr & !(align - 1)
+}
+
+unsafe fn payload(block: *mut Header) -> *mut u8 {
+    block.cast::<u8>().add(HEADER_SIZE)
+}
+
+unsafe fn footer(block: *mut Header) -> *mut Header {
+    payload(block).add((*block).size).cast::<Header>()
+}
+
+unsafe fn next_block(block: *mut Header) -> Option<*mut Header> {
+    let footer = footer(block);
+    if (*footer).special {
+        None
+    } else {
+        Some(footer.cast::<u8>().add(HEADER_SIZE).cast::<Header>())
+    }
+}
+
+unsafe fn previous_block(block: *mut Header) -> Option<*mut Header> {
+    if (*block).special {
+        return None;
+    }
+    let previous_footer = block.cast::<u8>().sub(HEADER_SIZE).cast::<Header>();
+    Some(
+        previous_footer
+            .cast::<u8>()
+            .sub((*previous_footer).size)
+            .sub(HEADER_SIZE)
+            .cast::<Header>(),
+    )
+}
+
+/// Write both boundary tags of the block starting at `block`.
+unsafe fn write_block(block: *mut Header, size: usize, alloced: bool, first: bool, last: bool) {
+    block.write(Header {
+        size,
+        special: first,
+        alloced,
+        #[cfg(feature = "heap-debug")]
+        magic: magic(size),
+    });
+    footer(block).write(Header {
+        size,
+        special: last,
+        alloced,
+        #[cfg(feature = "heap-debug")]
+        magic: magic(size),
+    });
+}
+
+// Each boundary tag carries a magic value mixed with the size of its block, so that an overrun
+// that reaches the next tag is caught the next time the block is looked at.
+#[cfg(feature = "heap-debug")]
+const HEADER_MAGIC: u32 = 0x4845_4150;
+
+// Written over freed memory, so that code still using it reads obviously wrong values
+#[cfg(feature = "heap-debug")]
+const POISON: u8 = 0xDD;
+
+// Written in the space between the end of an allocation and the end of its block
+#[cfg(feature = "heap-debug")]
+const TRAILER_CANARY: u8 = 0xCA;
+
+#[cfg(feature = "heap-debug")]
+fn magic(size: usize) -> u32 {
+    HEADER_MAGIC ^ size as u32
+}
+
+#[cfg(feature = "heap-debug")]
+fn corruption(block: *mut Header, what: &str) -> ! {
+    let (address, size) = unsafe { (payload(block) as usize, (*block).size) };
+    panic!("Heap corruption: {what} of the block at {address:#010x} ({size} bytes)")
+}
+
+/// Panic if the boundary tags of `block` were overwritten.
+#[cfg(feature = "heap-debug")]
+unsafe fn check_block(block: *mut Header) {
+    let header = &*block;
+    if header.magic != magic(header.size) {
+        corruption(block, "overwritten header");
+    }
+    let footer = &*footer(block);
+    if footer.magic != magic(footer.size)
+        || footer.size != header.size
+        || footer.alloced != header.alloced
+    {
+        corruption(block, "overwritten footer");
+    }
+}
+
+#[cfg(not(feature = "heap-debug"))]
+unsafe fn check_block(_block: *mut Header) {}
+
+/// Fill the end of the block that the allocation of `size` bytes doesn't use with a canary.
+#[cfg(feature = "heap-debug")]
+unsafe fn write_trailer(block: *mut Header, size: usize) {
+    core::ptr::write_bytes(
+        payload(block).add(size),
+        TRAILER_CANARY,
+        (*block).size - size,
+    );
+}
+
+#[cfg(not(feature = "heap-debug"))]
+unsafe fn write_trailer(_block: *mut Header, _size: usize) {}
+
+// Blocks are poisoned before they go in a free list, only their links are written over it
+#[cfg(feature = "heap-debug")]
+unsafe fn poison(block: *mut Header) {
+    core::ptr::write_bytes(payload(block), POISON, (*block).size);
+}
+
+#[cfg(not(feature = "heap-debug"))]
+unsafe fn poison(_block: *mut Header) {}
+
+/// Cut the free `block` in two, the first part having `size` bytes. Returns the second part.
+unsafe fn split(block: *mut Header, size: usize) -> *mut Header {
+    let total = (*block).size;
+    let first = (*block).special;
+    let last = (*footer(block)).special;
+
+    write_block(block, size, false, first, false);
+    let rest = footer(block).cast::<u8>().add(HEADER_SIZE).cast::<Header>();
+    write_block(rest, total - size - 2 * HEADER_SIZE, false, false, last);
+    rest
+}
+
+/// Whether the free `block` can hold `size` bytes aligned on `align`.
+unsafe fn fits(block: *mut Header, size: usize, align: usize) -> bool {
+    let padding = aligned_payload(block, align) - payload(block) as usize;
+    padding + size <= (*block).size
+}
+
+/// Address at which an allocation aligned on `align` can start in the free `block`, leaving
+/// either no gap or a gap large enough to become a free block on its own.
+unsafe fn aligned_payload(block: *mut Header, align: usize) -> usize {
+    let start = payload(block) as usize;
+    if start % align == 0 {
+        start
+    } else {
+        align_up(start + 2 * HEADER_SIZE + MIN_BLOCK_SIZE, align)
+    }
+}
+
 unsafe impl GlobalAlloc for Allocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let size_asked = layout.size();
-        let mut a = self.mmap_addr;
-        let mut header = a.cast::<Header>();
-        while (*header).alloced || (*header).size < (size_asked + 2 * size_of::<Header>()) {
-            if a.addr() > self.mmap_addr as usize && (*header).special {
-                panic!("Uh oh... No more memory !")
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let align = layout.align().max(GRANULARITY);
+        let size_asked = align_up(layout.size().max(MIN_BLOCK_SIZE), GRANULARITY);
+
+        let mut heap = self.heap.lock();
+        let mut block = heap.find_fit(size_asked, align);
+        if block.is_null() {
+            // Room for the region and block headers and the worst alignment padding
+            let needed = REGION_HEADER_SIZE + 4 * HEADER_SIZE + MIN_BLOCK_SIZE + align + size_asked;
+            if heap.grow(needed) {
+                block = heap.find_fit(size_asked, align);
             }
-            a = a
-                .add((*header).size)
-                .add(size_of::<Header>())
-                .add(size_of::<Header>());
-            header = a.cast::<Header>();
         }
+        if block.is_null() {
+            // Whoever asked decides what to do, see `out_of_memory`
+            return core::ptr::null_mut();
+        }
+        heap.remove_free(block);
 
-        let updated_header = Header {
-            special: false,
-            alloced: true,
-            size: size_asked,
-        };
-        let new_free_header = Header {
-            special: false,
-            alloced: false,
-            size: (*header).size - size_asked - 2 * size_of::<Header>(),
-        };
-
-        // at first because the header size value will be rewrote
-        let last_free_header = (header as *mut u8)
-            .add((*header).size)
-            .add(size_of::<Header>()) as *mut Header;
+        // Give the space in front of the aligned address back as a free block. Free blocks are
+        // always merged, so the blocks around this one are allocated.
+        let padding = aligned_payload(block, align) - payload(block) as usize;
+        if padding != 0 {
+            let front = block;
+            block = split(block, padding - 2 * HEADER_SIZE);
+            heap.push_free(front);
+        }
 
-        (*last_free_header).alloced = false;
-        (*last_free_header).size = new_free_header.size;
-        // dont touch (*last_free_header).special
+        // And the space after the allocation, if it is worth it
+        if (*block).size >= size_asked + 2 * HEADER_SIZE + MIN_BLOCK_SIZE {
+            let rest = split(block, size_asked);
+            heap.push_free(rest);
+        }
 
-        (*header).alloced = true;
-        (*header).size = updated_header.size;
-        //don't touch (*header).special
+        (*block).alloced = true;
+        (*footer(block)).alloced = true;
+        write_trailer(block, layout.size());
 
-        let res = a.add(size_of::<Header>());
+        heap.in_use += (*block).size;
+        heap.peak_in_use = heap.peak_in_use.max(heap.in_use);
+        heap.allocations += 1;
 
-        let a = res.add(size_asked);
-        a.copy_from_nonoverlapping(
-            (&updated_header as *const Header) as *const u8,
-            size_of::<Header>(),
-        );
+        payload(block)
+    }
 
-        let a = a.add(size_of::<Header>());
-        a.copy_from_nonoverlapping(
-            (&new_free_header as *const Header) as *const u8,
-            size_of::<Header>(),
-        );
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // The blocks around this one may change
+        let mut heap = self.heap.lock();
+        let block = ptr.sub(HEADER_SIZE).cast::<Header>();
+        heap.check_free(block, layout.size());
+        if !(*block).alloced {
+            return;
+        }
+        heap.in_use -= (*block).size;
+        heap.allocations -= 1;
 
-        res
+        (*block).alloced = false;
+        (*footer(block)).alloced = false;
+        heap.release(block);
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: core::alloc::Layout) {
-        let header = ptr.offset(-1 * size_of::<Header>() as isize);
-        let header = header as *mut Header;
-        if !(*header).alloced {
-            return;
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let size_asked = align_up(new_size.max(MIN_BLOCK_SIZE), GRANULARITY);
+        {
+            let mut heap = self.heap.lock();
+            let block = ptr.sub(HEADER_SIZE).cast::<Header>();
+            heap.check_free(block, layout.size());
+            if heap.resize(block, size_asked) {
+                write_trailer(block, new_size);
+                return ptr;
+            }
         }
 
-        let header_size = (*header).size;
-        (*header).alloced = false;
-        let snd_header = ptr.add((*header).size) as *mut Header;
-        (*snd_header).alloced = false;
+        // The block can't grow where it is, move it
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
 
-        let next_header_exists = !(*snd_header).special;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr::addr_of_mut;
 
-        if !(*snd_header).special {
-            let next_header = ptr
-                .add(header_size) // first header
-                .add(size_of::<Header>())
-                .cast::<Header>();
+    // The tests each make a heap of their own on this, so they know every block in it
+    #[repr(align(4096))]
+    struct Buffer([u8; 4 * FRAME_SIZE]);
 
-            if !(*next_header).alloced {
-                // merge
-                let next_header = next_header
-                    .cast::<u8>()
-                    .add((*next_header).size)
-                    .add(size_of::<Header>()) as *mut Header;
+    static mut BUFFER: Buffer = Buffer([0; 4 * FRAME_SIZE]);
 
-                // add middle header
-                (*header).size += (*next_header).size + 2 * size_of::<Header>();
-                (*next_header).size = (*header).size;
-            }
+    fn local_heap() -> Allocator {
+        let allocator = Allocator::new();
+        unsafe {
+            let start = addr_of_mut!(BUFFER.0) as usize;
+            allocator
+                .heap
+                .lock()
+                .add_region(start, start + size_of::<Buffer>());
         }
+        allocator
+    }
 
-        if !(*header).special {
-            let last_header =
-                (header as *const u8).offset(-1 * size_of::<Header>() as isize) as *mut Header; // second header
-            let last_header = (last_header as *const u8)
-                .offset(-1 * (*last_header).size as isize)
-                .offset(-1 * size_of::<Header>() as isize)
-                as *mut Header;
-
-            if !(*last_header).alloced {
-                if next_header_exists && {
-                    let next_header = ptr.add(header_size).add(size_of::<Header>()) as *mut Header;
-                    !(*next_header).alloced
-                } {
-                    let next_header = ptr.add(header_size).add(size_of::<Header>()) as *mut Header;
-
-                    let next_header = next_header
-                        .cast::<u8>()
-                        .add((*next_header).size)
-                        .add(size_of::<Header>())
-                        as *mut Header;
-
-                    // add 4 header
-                    // - last from last_header
-                    // - 2 from header (but 1 already has been added)
-                    // - first from next_header (but has already been added)
-                    (*last_header).size += (*next_header).size + 2 * size_of::<Header>();
-                    (*next_header).size = (*last_header).size;
-                } else {
-                    // add middle header
-                    (*last_header).size += (*snd_header).size + 2 * size_of::<Header>();
-                    (*snd_header).size = (*last_header).size;
-                }
+    // Size of the single block of an empty local heap
+    const WHOLE: usize = size_of::<Buffer>() - REGION_HEADER_SIZE - 2 * HEADER_SIZE;
+
+    fn blocks(allocator: &Allocator) -> Vec<(bool, usize)> {
+        allocator
+            .blocks()
+            .map(|block| (block.allocated, block.size))
+            .collect()
+    }
+
+    fn free_list_len(allocator: &Allocator) -> usize {
+        let heap = allocator.heap.lock();
+        let mut len = 0;
+        for &head in heap.free_lists.iter() {
+            let mut block = head;
+            while !block.is_null() {
+                len += 1;
+                block = unsafe { (*links(block)).next };
             }
         }
+        len
+    }
+
+    #[test_case]
+    fn align_up_rounds_to_the_next_multiple() {
+        assert_eq!(align_up(0, 16), 0);
+        assert_eq!(align_up(1, 16), 16);
+        assert_eq!(align_up(16, 16), 16);
+        assert_eq!(align_up(17, 16), 32);
+        assert_eq!(align_up(4095, 4096), 4096);
+        assert_eq!(align_down(4095, 4096), 0);
+    }
+
+    #[test_case]
+    fn global_allocations_are_aligned() {
+        for align in [16, 64, 4096] {
+            let layout = Layout::from_size_align(24, align).expect("valid layout");
+            let ptr = unsafe { alloc::alloc::alloc(layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0);
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+    }
+
+    #[test_case]
+    fn aligned_payload_leaves_room_for_a_free_block() {
+        let allocator = local_heap();
+        let block = unsafe { first_block(allocator.heap.lock().first_region) };
+        let start = unsafe { payload(block) as usize };
+
+        assert_eq!(unsafe { aligned_payload(block, GRANULARITY) }, start);
+        let aligned = unsafe { aligned_payload(block, 4096) };
+        assert_eq!(aligned % 4096, 0);
+        assert!(aligned - start >= 2 * HEADER_SIZE + MIN_BLOCK_SIZE);
+    }
+
+    #[test_case]
+    fn padding_becomes_a_free_block() {
+        let allocator = local_heap();
+        let layout = Layout::from_size_align(64, 4096).expect("valid layout");
+        let region = allocator.heap.lock().first_region as usize;
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(ptr as usize, region + 4096);
+
+        let padding = 4096 - REGION_HEADER_SIZE - 3 * HEADER_SIZE;
+        let rest = WHOLE - padding - 64 - 4 * HEADER_SIZE;
+        assert_eq!(
+            blocks(&allocator),
+            [(false, padding), (true, 64), (false, rest)]
+        );
+        assert_eq!(free_list_len(&allocator), 2);
+
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert_eq!(blocks(&allocator), [(false, WHOLE)]);
+    }
+
+    #[test_case]
+    fn remainder_is_split_only_if_large_enough() {
+        let allocator = local_heap();
+        let layout = Layout::from_size_align(64, 16).expect("valid layout");
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(
+            blocks(&allocator),
+            [(true, 64), (false, WHOLE - 64 - 2 * HEADER_SIZE)]
+        );
+        unsafe { allocator.dealloc(ptr, layout) };
+
+        // What would be left can't hold a free block, the allocation takes it all
+        let size = WHOLE - 2 * HEADER_SIZE - MIN_BLOCK_SIZE + GRANULARITY;
+        let layout = Layout::from_size_align(size, 16).expect("valid layout");
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(blocks(&allocator), [(true, WHOLE)]);
+        assert_eq!(free_list_len(&allocator), 0);
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    #[test_case]
+    fn free_merges_with_both_neighbours() {
+        let allocator = local_heap();
+        let layout = Layout::from_size_align(64, 16).expect("valid layout");
+        let [a, b, c] = [(); 3].map(|_| unsafe { allocator.alloc(layout) });
+
+        unsafe {
+            allocator.dealloc(a, layout);
+            allocator.dealloc(c, layout);
+        }
+        let rest = WHOLE - 2 * 64 - 4 * HEADER_SIZE;
+        assert_eq!(blocks(&allocator), [(false, 64), (true, 64), (false, rest)]);
+        assert_eq!(free_list_len(&allocator), 2);
+
+        unsafe { allocator.dealloc(b, layout) };
+        assert_eq!(blocks(&allocator), [(false, WHOLE)]);
+        assert_eq!(free_list_len(&allocator), 1);
     }
 }
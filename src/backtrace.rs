@@ -0,0 +1,220 @@
+use core::arch::asm;
+use core::fmt;
+use core::mem::size_of;
+
+use crate::boot::BootInfo;
+use crate::memory::paging;
+use crate::memory::KERNEL_OFFSET;
+
+// Stop there if the chain of frames seems to go on forever
+const MAX_FRAMES: usize = 64;
+
+// Type of a symbol, in the low bits of its info field
+const STT_FUNC: u8 = 2;
+
+// https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.symtab.html
+#[allow(unused)]
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ElfSymbol {
+    name: u32,
+    value: u32,
+    size: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+}
+
+struct SymbolTable {
+    symbols: &'static [ElfSymbol],
+    strings: &'static [u8],
+}
+
+impl SymbolTable {
+    /// The function `addr` is in, and how far into it.
+    fn lookup(&self, addr: u32) -> Option<(&'static str, u32)> {
+        let symbol = self.symbols.iter().find(|symbol| {
+            symbol.info & 0xf == STT_FUNC
+                && symbol.value <= addr
+                && addr - symbol.value < symbol.size.max(1)
+        })?;
+        let name = self.strings.get(symbol.name as usize..)?;
+        let len = name.iter().position(|&c| c == 0)?;
+        let name = core::str::from_utf8(&name[..len]).ok()?;
+        Some((name, addr - symbol.value))
+    }
+}
+
+// The kernel symbols, if the bootloader loaded them
+static mut SYMBOLS: Option<SymbolTable> = None;
+
+/// Find the symbol table of the kernel in the ELF sections the bootloader gave. The sections are
+/// read through the direct map, so paging must be initialized.
+pub fn init(boot_info: &BootInfo) {
+    let Some(sections) = boot_info.elf_sections() else {
+        return;
+    };
+    let Some(symtab) = sections.find(".symtab") else {
+        return;
+    };
+    // The names of the symbols are in the section the symbol table links to
+    let Some(strtab) = sections.iter().nth(symtab.link as usize) else {
+        return;
+    };
+    let (Some(symbols), Some(strings)) = (symtab.data(), strtab.data()) else {
+        return;
+    };
+    if symtab.entsize as usize != size_of::<ElfSymbol>() {
+        return;
+    }
+    let symbols = unsafe {
+        core::slice::from_raw_parts(
+            symbols.as_ptr() as *const ElfSymbol,
+            symbols.len() / size_of::<ElfSymbol>(),
+        )
+    };
+    unsafe { *core::ptr::addr_of_mut!(SYMBOLS) = Some(SymbolTable { symbols, strings }) }
+}
+
+/// Prints a legacy mangled Rust symbol, like `_ZN10webserv_os4time6uptime17h0123456789abcdefE`, as
+/// the path it stands for, `webserv_os::time::uptime`. Other names are printed as they are.
+struct Demangled(&'static str);
+
+/// The first length-prefixed element of a mangled path, and what follows it.
+fn split_element(path: &str) -> Option<(&str, &str)> {
+    let digits = path.bytes().take_while(u8::is_ascii_digit).count();
+    let len = path[..digits].parse::<usize>().ok()?;
+    let element = path.get(digits..digits + len)?;
+    Some((element, &path[digits + len..]))
+}
+
+impl fmt::Display for Demangled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(path) = self
+            .0
+            .strip_prefix("_ZN")
+            .and_then(|name| name.strip_suffix('E'))
+        else {
+            return f.write_str(self.0);
+        };
+        // Check the whole path before printing anything of it
+        let mut rest = path;
+        while !rest.is_empty() {
+            match split_element(rest) {
+                Some((_, after)) => rest = after,
+                None => return f.write_str(self.0),
+            }
+        }
+
+        let mut rest = path;
+        let mut first = true;
+        while let Some((element, after)) = split_element(rest) {
+            rest = after;
+            // The last element is a hash of the symbol
+            if rest.is_empty() && element.starts_with('h') && element.len() == 17 {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+            write_element(f, element)?;
+        }
+        Ok(())
+    }
+}
+
+// Characters that can't be in a symbol are written as `$..$` escapes, and `::` inside an
+// element as `..`.
+fn write_element(f: &mut fmt::Formatter<'_>, mut element: &str) -> fmt::Result {
+    // A leading underscore only keeps an element from starting with `$`
+    if element.starts_with("_$") {
+        element = &element[1..];
+    }
+    while !element.is_empty() {
+        if let Some(escaped) = element.strip_prefix('$') {
+            let Some(end) = escaped.find('$') else {
+                return f.write_str(element);
+            };
+            match &escaped[..end] {
+                "SP" => f.write_str("@")?,
+                "BP" => f.write_str("*")?,
+                "RF" => f.write_str("&")?,
+                "LT" => f.write_str("<")?,
+                "GT" => f.write_str(">")?,
+                "LP" => f.write_str("(")?,
+                "RP" => f.write_str(")")?,
+                "C" => f.write_str(",")?,
+                code => match code
+                    .strip_prefix('u')
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32)
+                {
+                    Some(c) => write!(f, "{c}")?,
+                    None => write!(f, "${code}$")?,
+                },
+            }
+            element = &escaped[end + 1..];
+        } else if let Some(rest) = element.strip_prefix("..") {
+            f.write_str("::")?;
+            element = rest;
+        } else {
+            let len = element[1..]
+                .find(['$', '.'])
+                .map_or(element.len(), |i| i + 1);
+            f.write_str(&element[..len])?;
+            element = &element[len..];
+        }
+    }
+    Ok(())
+}
+
+// Frame pointers are always kept (see target.json), so every function starts by pushing the ebp
+// of its caller and pointing ebp to it, with its return address right above.
+fn frame_is_readable(ebp: u32) -> bool {
+    let ebp = ebp as usize;
+    ebp >= KERNEL_OFFSET
+        && ebp & 3 == 0
+        && paging::translate(ebp).is_some()
+        && paging::translate(ebp + 7).is_some()
+}
+
+/// Print the return addresses of the functions that lead to the caller, with the function they
+/// are in when the symbol table is known.
+pub fn print() {
+    let mut ebp: u32;
+    unsafe {
+        asm!(r#"
+            .att_syntax
+            mov %ebp, %eax
+            "#,
+            out("eax") ebp);
+    }
+    let symbols = unsafe { (*core::ptr::addr_of!(SYMBOLS)).as_ref() };
+
+    emergency_println!("Backtrace:");
+    for i in 0..MAX_FRAMES {
+        // boot.S clears ebp before calling kernel_main, that's the end of the chain
+        if ebp == 0 || !frame_is_readable(ebp) {
+            break;
+        }
+        let (next, return_address) = unsafe {
+            let frame = ebp as *const u32;
+            (*frame, *frame.add(1))
+        };
+        // Look up the call instruction rather than what follows it, which may be another function
+        match symbols.and_then(|symbols| symbols.lookup(return_address.wrapping_sub(1))) {
+            Some((name, offset)) => emergency_println!(
+                "  {i:2}: {return_address:#010x} {}+{:#x}",
+                Demangled(name),
+                offset + 1
+            ),
+            None => emergency_println!("  {i:2}: {return_address:#010x}"),
+        }
+        // The stack grows down, callers' frames are above
+        if next <= ebp {
+            break;
+        }
+        ebp = next;
+    }
+}
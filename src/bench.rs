@@ -0,0 +1,85 @@
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::allocator::{Allocator, FitPolicy};
+use crate::memory::frame::{self, FRAME_SIZE};
+use crate::memory::phys_to_virt;
+use crate::time::Instant;
+
+// Allocations kept alive at the same time, and how many are made in total
+const LIVE_ALLOCATIONS: usize = 2000;
+const ALLOCATIONS: usize = 20000;
+
+// Memory of the heap each policy runs on, well over what the allocations need at once so that
+// it never grows
+const HEAP_SIZE: usize = 4 * 1024 * 1024;
+
+/// Run the same allocations with each fit policy and print how long they took. Each policy gets
+/// a new heap of its own, so neither runs on the blocks the other or the kernel left behind.
+pub fn heap() {
+    println!("Heap benchmark, {ALLOCATIONS} allocations with {LIVE_ALLOCATIONS} alive:");
+    for policy in [FitPolicy::FirstFit, FitPolicy::SegregatedFit] {
+        let frames = HEAP_SIZE / FRAME_SIZE;
+        let Some(first) = frame::allocate_contiguous(frames) else {
+            println!("    No memory for the heap of {policy:?}.");
+            return;
+        };
+        let region = phys_to_virt(first.start_address());
+        let heap = unsafe { Allocator::with_region(policy, region, region + HEAP_SIZE) };
+
+        let start = Instant::now();
+        allocate_and_free(&heap);
+        println!("    {policy:?}: {:?}", start.elapsed());
+
+        frame::deallocate_contiguous(first, frames);
+    }
+}
+
+// Like a busy server: many small buffers of mixed sizes, a few large ones, freed in no
+// particular order, and some that keep growing.
+fn allocate_and_free(heap: &Allocator) {
+    let mut random = XorShift(0x2545_f491);
+    // On the kernel heap, allocated once before the first allocation of `heap`
+    let mut live: Vec<(*mut u8, Layout)> = Vec::with_capacity(LIVE_ALLOCATIONS);
+    for _ in 0..ALLOCATIONS {
+        let size = match random.number() % 16 {
+            0 => 1024 + random.number() as usize % 4096,
+            _ => 8 + random.number() as usize % 256,
+        };
+        let mut layout = Layout::from_size_align(size, 1).expect("valid layout");
+        let mut buffer = unsafe { heap.alloc(layout) };
+        if buffer.is_null() {
+            continue;
+        }
+        unsafe { buffer.write_bytes(0, size) };
+        if random.number() & 7 == 0 {
+            let grown = unsafe { heap.realloc(buffer, layout, size + 64) };
+            if !grown.is_null() {
+                buffer = grown;
+                layout = Layout::from_size_align(size + 64, 1).expect("valid layout");
+            }
+        }
+        if live.len() < LIVE_ALLOCATIONS {
+            live.push((buffer, layout));
+        } else {
+            let i = random.number() as usize % live.len();
+            let (old, old_layout) = core::mem::replace(&mut live[i], (buffer, layout));
+            unsafe { heap.dealloc(old, old_layout) };
+        }
+    }
+    for (buffer, layout) in live {
+        unsafe { heap.dealloc(buffer, layout) };
+    }
+}
+
+// The same numbers every run, so both policies get the same allocations
+struct XorShift(u32);
+
+impl XorShift {
+    fn number(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
@@ -1,5 +1,92 @@
-use core::mem::size_of;
+use core::ffi::{c_char, CStr};
+use core::fmt;
+use core::mem::{size_of, size_of_val};
+use core::ptr::read_unaligned;
 
+use crate::memory::{phys_to_virt, virt_to_phys, KERNEL_OFFSET};
+use crate::multiboot2::{Multiboot2Info, MULTIBOOT2_BOOTLOADER_MAGIC};
+
+/// What the bootloader tells the kernel, with either version of the multiboot protocol.
+#[derive(Clone, Copy)]
+pub enum BootInfo {
+    Multiboot(&'static MultibootInfo),
+    Multiboot2(&'static Multiboot2Info),
+}
+
+impl BootInfo {
+    /// Which structure the bootloader gave depends on the magic value it left in eax. `info` is
+    /// the virtual address of the structure.
+    pub unsafe fn new(magic: u32, info: usize) -> Option<Self> {
+        match magic {
+            MULTIBOOT_BOOTLOADER_MAGIC => Some(Self::Multiboot(&*(info as *const MultibootInfo))),
+            MULTIBOOT2_BOOTLOADER_MAGIC => {
+                Some(Self::Multiboot2(&*(info as *const Multiboot2Info)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn command_line(&self) -> Option<&'static str> {
+        match self {
+            Self::Multiboot(info) => info.command_line(),
+            Self::Multiboot2(info) => info.command_line(),
+        }
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&'static str> {
+        match self {
+            Self::Multiboot(info) => info.boot_loader_name(),
+            Self::Multiboot2(info) => info.boot_loader_name(),
+        }
+    }
+
+    pub fn elf_sections(&self) -> Option<ElfSections> {
+        match self {
+            Self::Multiboot(info) => info.elf_sections(),
+            Self::Multiboot2(info) => info.elf_sections(),
+        }
+    }
+
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        match self {
+            Self::Multiboot(info) => info.framebuffer(),
+            Self::Multiboot2(info) => info.framebuffer(),
+        }
+    }
+
+    /// The BIOS disks, only Multiboot gives them.
+    pub fn drives(&self) -> Option<Drives> {
+        match self {
+            Self::Multiboot(info) => info.drives(),
+            Self::Multiboot2(_) => None,
+        }
+    }
+
+    /// The APM BIOS interface, only read from Multiboot.
+    pub fn apm_table(&self) -> Option<&'static ApmTable> {
+        match self {
+            Self::Multiboot(info) => info.apm_table(),
+            Self::Multiboot2(_) => None,
+        }
+    }
+
+    /// A copy of the ACPI RSDP, only Multiboot2 gives one.
+    pub fn rsdp(&self) -> Option<&'static [u8]> {
+        match self {
+            Self::Multiboot(_) => None,
+            Self::Multiboot2(info) => info.rsdp(),
+        }
+    }
+
+    pub fn loop_through_usable_memory(&self, f: impl FnMut(u64, u64)) {
+        match self {
+            Self::Multiboot(info) => info.loop_through_usable_memory(f),
+            Self::Multiboot2(info) => info.loop_through_usable_memory(f),
+        }
+    }
+}
+
+// https://www.gnu.org/software/grub/manual/multiboot/multiboot.html#Boot-information-format
 #[allow(unused)]
 #[repr(C)]
 pub struct MultibootInfo {
@@ -35,7 +122,7 @@ pub struct MultibootInfo {
 
     };
         */
-    padding1: [u8; 16],
+    syms: [u32; 4],
     /* Memory Mapping buffer */
     mmap_length: u32,
     mmap_addr: u32,
@@ -48,7 +135,7 @@ pub struct MultibootInfo {
     config_table: u32,
 
     /* Boot Loader Name */
-    boot_loader_name: *const u8,
+    boot_loader_name: u32,
 
     /* APM table */
     apm_table: u32,
@@ -71,31 +158,585 @@ pub struct MultibootInfo {
     #define MULTIBOOT_FRAMEBUFFER_TYPE_EGA_TEXT     2 */
     framebuffer_type: u8,
 
-    padding2: [u8; 6],
+    /* Palette or RGB fields positions, depending on framebuffer_type */
+    color_info: [u8; 6],
+}
+
+// Flags telling which fields of MultibootInfo are valid
+const MULTIBOOT_INFO_CMDLINE: u32 = 1 << 2;
+const MULTIBOOT_INFO_MODS: u32 = 1 << 3;
+const MULTIBOOT_INFO_AOUT_SYMS: u32 = 1 << 4;
+const MULTIBOOT_INFO_ELF_SHDR: u32 = 1 << 5;
+const MULTIBOOT_INFO_MEM_MAP: u32 = 1 << 6;
+const MULTIBOOT_INFO_DRIVE_INFO: u32 = 1 << 7;
+const MULTIBOOT_INFO_BOOT_LOADER_NAME: u32 = 1 << 9;
+const MULTIBOOT_INFO_APM_TABLE: u32 = 1 << 10;
+const MULTIBOOT_INFO_FRAMEBUFFER_INFO: u32 = 1 << 12;
+
+// Real mode IVT and BIOS data area
+const FIRST_PAGE_END: u64 = 0x1000;
+
+// We can only address the first 4 GiB
+const ADDRESSABLE_END: u64 = 0x1_0000_0000;
+
+// The bootloader gives physical addresses, which we reach through the direct map. What they
+// point to is reserved before the frame allocator starts, so it stays valid.
+unsafe fn from_phys<T>(phys: u32) -> &'static T {
+    &*(phys_to_virt(phys as usize) as *const T)
+}
+
+unsafe fn c_string(phys: u32) -> &'static CStr {
+    CStr::from_ptr(phys_to_virt(phys as usize) as *const c_char)
+}
+
+unsafe fn c_string_range(phys: u32) -> (u64, u64) {
+    let len = c_string(phys).to_bytes_with_nul().len();
+    (phys as u64, phys as u64 + len as u64)
+}
+
+/// Memory in use whatever the bootloader says: the first page and the kernel image.
+pub fn always_reserved() -> [(u64, u64); 2] {
+    #[allow(unused_unsafe)]
+    unsafe {
+        let kernel_start = virt_to_phys(core::ptr::addr_of!(crate::KERNEL_START) as usize);
+        let kernel_end = virt_to_phys(core::ptr::addr_of!(crate::KERNEL_END) as usize);
+        [
+            (0, FIRST_PAGE_END),
+            (kernel_start as u64, kernel_end as u64),
+        ]
+    }
+}
+
+fn loop_through_unreserved_inner(
+    start: u64,
+    end: u64,
+    mut reserved: impl Iterator<Item = (u64, u64)> + Clone,
+    f: &mut impl FnMut(u64, u64),
+) {
+    if start >= end {
+        return;
+    }
+    match reserved.next() {
+        None => f(start, end),
+        Some((reserved_start, reserved_end)) => {
+            loop_through_unreserved_inner(start, end.min(reserved_start), reserved.clone(), f);
+            loop_through_unreserved_inner(start.max(reserved_end), end, reserved, f);
+        }
+    }
+}
+
+/// Call `f` with the start and end of every piece of the available memory from `start` to `end`
+/// that is below 4 GiB and outside of the `reserved` ranges, in ascending order.
+pub fn loop_through_unreserved(
+    start: u64,
+    end: u64,
+    reserved: impl Iterator<Item = (u64, u64)> + Clone,
+    f: &mut impl FnMut(u64, u64),
+) {
+    let start = start.min(ADDRESSABLE_END);
+    let end = end.min(ADDRESSABLE_END);
+    loop_through_unreserved_inner(start, end, reserved, f);
 }
 
 impl MultibootInfo {
-    pub fn check_flags_for_memmap(&self) -> bool {
-        (self.flags >> 6 & 0x1) == 0
+    fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// The command line given to the kernel in the bootloader configuration.
+    pub fn command_line(&self) -> Option<&'static str> {
+        if !self.has_flag(MULTIBOOT_INFO_CMDLINE) {
+            return None;
+        }
+        unsafe { c_string(self.cmdline).to_str().ok() }
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&'static str> {
+        if !self.has_flag(MULTIBOOT_INFO_BOOT_LOADER_NAME) {
+            return None;
+        }
+        unsafe { c_string(self.boot_loader_name).to_str().ok() }
+    }
+
+    /// The files the bootloader loaded along with the kernel.
+    pub fn modules(&self) -> Option<&'static [MultibootModule]> {
+        if !self.has_flag(MULTIBOOT_INFO_MODS) {
+            return None;
+        }
+        unsafe {
+            Some(core::slice::from_raw_parts(
+                phys_to_virt(self.mods_addr as usize) as *const MultibootModule,
+                self.mods_count as usize,
+            ))
+        }
+    }
+
+    /// The section headers of the kernel ELF file.
+    pub fn elf_sections(&self) -> Option<ElfSections> {
+        // The same field holds the a.out symbol table instead when bit 4 is set
+        if !self.has_flag(MULTIBOOT_INFO_ELF_SHDR) || self.has_flag(MULTIBOOT_INFO_AOUT_SYMS) {
+            return None;
+        }
+        let [count, entry_size, addr, string_table] = self.syms;
+        unsafe {
+            ElfSections::from_raw(phys_to_virt(addr as usize), count, entry_size, string_table)
+        }
+    }
+
+    /// The memory map given by the BIOS.
+    pub fn memory_map(&self) -> Option<MemoryMap> {
+        if !self.has_flag(MULTIBOOT_INFO_MEM_MAP) {
+            return None;
+        }
+        let start = phys_to_virt(self.mmap_addr as usize);
+        Some(MemoryMap {
+            next: start,
+            end: start + self.mmap_length as usize,
+        })
+    }
+
+    /// The BIOS disks.
+    pub fn drives(&self) -> Option<Drives> {
+        if !self.has_flag(MULTIBOOT_INFO_DRIVE_INFO) {
+            return None;
+        }
+        let start = phys_to_virt(self.drives_addr as usize);
+        Some(Drives {
+            next: start,
+            end: start + self.drives_length as usize,
+        })
+    }
+
+    /// The Advanced Power Management BIOS interface.
+    pub fn apm_table(&self) -> Option<&'static ApmTable> {
+        if !self.has_flag(MULTIBOOT_INFO_APM_TABLE) {
+            return None;
+        }
+        unsafe { Some(from_phys(self.apm_table)) }
+    }
+
+    /// The video mode the bootloader set up.
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        if !self.has_flag(MULTIBOOT_INFO_FRAMEBUFFER_INFO) {
+            return None;
+        }
+        let c = self.color_info;
+        let kind = match self.framebuffer_type {
+            MULTIBOOT_FRAMEBUFFER_TYPE_INDEXED => FramebufferKind::Indexed {
+                palette_addr: u32::from_le_bytes([c[0], c[1], c[2], c[3]]),
+                palette_colors: u16::from_le_bytes([c[4], c[5]]),
+            },
+            MULTIBOOT_FRAMEBUFFER_TYPE_RGB => FramebufferKind::Rgb {
+                red_position: c[0],
+                red_size: c[1],
+                green_position: c[2],
+                green_size: c[3],
+                blue_position: c[4],
+                blue_size: c[5],
+            },
+            MULTIBOOT_FRAMEBUFFER_TYPE_EGA_TEXT => FramebufferKind::EgaText,
+            _ => return None,
+        };
+        Some(Framebuffer {
+            addr: self.framebuffer_addr,
+            pitch: self.framebuffer_pitch,
+            width: self.framebuffer_width,
+            height: self.framebuffer_height,
+            bpp: self.framebuffer_bpp,
+            kind,
+        })
+    }
+
+    /// Ranges of physical memory that are in use before memory is managed: the first page, the
+    /// kernel image, the multiboot structures, the modules and the ELF sections the bootloader
+    /// loaded outside the kernel image.
+    fn reserved_ranges(&self) -> impl Iterator<Item = (u64, u64)> + Clone + '_ {
+        let info = virt_to_phys(self as *const MultibootInfo as usize) as u64;
+        let range = |flag, start: u32, len: usize| {
+            self.has_flag(flag)
+                .then_some((start as u64, start as u64 + len as u64))
+        };
+
+        let fixed = unsafe {
+            [
+                Some((info, info + size_of::<MultibootInfo>() as u64)),
+                range(
+                    MULTIBOOT_INFO_MEM_MAP,
+                    self.mmap_addr,
+                    self.mmap_length as usize,
+                ),
+                self.has_flag(MULTIBOOT_INFO_CMDLINE)
+                    .then(|| c_string_range(self.cmdline)),
+                self.has_flag(MULTIBOOT_INFO_BOOT_LOADER_NAME)
+                    .then(|| c_string_range(self.boot_loader_name)),
+                range(
+                    MULTIBOOT_INFO_MODS,
+                    self.mods_addr,
+                    self.mods_count as usize * size_of::<MultibootModule>(),
+                ),
+                self.elf_sections().map(|sections| {
+                    let start = self.syms[2] as u64;
+                    (start, start + size_of_val(sections.headers) as u64)
+                }),
+                range(
+                    MULTIBOOT_INFO_DRIVE_INFO,
+                    self.drives_addr,
+                    self.drives_length as usize,
+                ),
+                range(
+                    MULTIBOOT_INFO_APM_TABLE,
+                    self.apm_table,
+                    size_of::<ApmTable>(),
+                ),
+            ]
+        };
+
+        let modules = self.modules().unwrap_or(&[]).iter();
+        let sections = self
+            .elf_sections()
+            .map(|sections| sections.headers)
+            .unwrap_or(&[]);
+        always_reserved()
+            .into_iter()
+            .chain(fixed.into_iter().flatten())
+            .chain(modules.map(|module| (module.start() as u64, module.end() as u64)))
+            .chain(sections.iter().filter_map(ElfSectionHeader::physical_range))
+    }
+
+    /// Call `f` with the start and end of every piece of available memory below 4 GiB that
+    /// nothing uses yet, in ascending order inside each memory map entry.
+    pub fn loop_through_usable_memory(&self, mut f: impl FnMut(u64, u64)) {
+        let Some(memory_map) = self.memory_map() else {
+            return;
+        };
+        for mmap in memory_map {
+            if mmap.memory_type() != MultibootMemoryMappedType::Available {
+                continue;
+            }
+            let end = mmap.addr() + mmap.len();
+            loop_through_unreserved(mmap.addr(), end, self.reserved_ranges(), &mut f);
+        }
+    }
+}
+
+#[repr(C)]
+pub struct MultibootModule {
+    mod_start: u32,
+    mod_end: u32,
+    string: u32,
+    reserved: u32,
+}
+
+impl MultibootModule {
+    /// Physical address of the start of the module.
+    pub fn start(&self) -> u32 {
+        self.mod_start
+    }
+
+    /// Physical address of the end of the module.
+    pub fn end(&self) -> u32 {
+        self.mod_end
+    }
+
+    /// The string the bootloader associated with the module, usually its command line.
+    pub fn string(&self) -> Option<&'static str> {
+        if self.string == 0 {
+            return None;
+        }
+        unsafe { c_string(self.string).to_str().ok() }
+    }
+}
+
+// Type of the sections that take no room in the file, like .bss
+const SHT_NOBITS: u32 = 8;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ElfSectionHeader {
+    pub name: u32,
+    pub r#type: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u32,
+    pub entsize: u32,
+}
+
+impl ElfSectionHeader {
+    /// The content of the section, if it is in memory. Sections of the kernel image are at the
+    /// address they are linked at, the bootloader loads the others in physical memory.
+    pub fn data(&self) -> Option<&'static [u8]> {
+        if self.addr == 0 || self.r#type == SHT_NOBITS {
+            return None;
+        }
+        let addr = self.addr as usize;
+        let virt = if addr >= KERNEL_OFFSET {
+            addr
+        } else {
+            phys_to_virt(addr)
+        };
+        unsafe {
+            Some(core::slice::from_raw_parts(
+                virt as *const u8,
+                self.size as usize,
+            ))
+        }
+    }
+
+    /// Where the bootloader loaded the section, if it isn't part of the kernel image.
+    pub fn physical_range(&self) -> Option<(u64, u64)> {
+        let addr = self.addr as u64;
+        (self.addr != 0 && (self.addr as usize) < KERNEL_OFFSET)
+            .then_some((addr, addr + self.size as u64))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ElfSections {
+    headers: &'static [ElfSectionHeader],
+    // Index of the section holding the section names
+    string_table: usize,
+}
+
+impl ElfSections {
+    /// The `count` section headers of `entry_size` bytes at the virtual address `headers`.
+    pub unsafe fn from_raw(
+        headers: usize,
+        count: u32,
+        entry_size: u32,
+        string_table: u32,
+    ) -> Option<Self> {
+        if entry_size as usize != size_of::<ElfSectionHeader>() {
+            return None;
+        }
+        Some(Self {
+            headers: core::slice::from_raw_parts(
+                headers as *const ElfSectionHeader,
+                count as usize,
+            ),
+            string_table: string_table as usize,
+        })
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'static, ElfSectionHeader> {
+        self.headers.iter()
+    }
+
+    pub fn name(&self, header: &ElfSectionHeader) -> Option<&'static str> {
+        let names = self.headers.get(self.string_table)?.data()?;
+        let name = names.get(header.name as usize..)?;
+        let len = name.iter().position(|&c| c == 0)?;
+        core::str::from_utf8(&name[..len]).ok()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&'static ElfSectionHeader> {
+        self.iter().find(|header| self.name(header) == Some(name))
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryMap {
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for MemoryMap {
+    type Item = MultibootMMapEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next + size_of::<MultibootMMapEntry>() > self.end {
+            return None;
+        }
+        let entry = unsafe { read_unaligned(self.next as *const MultibootMMapEntry) };
+        // The size of an entry doesn't count the size field itself
+        self.next += entry.size() as usize + size_of::<u32>();
+        Some(entry)
     }
+}
 
-    pub unsafe fn get_mmap_addrs(&self) -> &[MultibootMMapEntry] {
-        let num_mmap_addr = self.mmap_length as usize / size_of::<MultibootMMapEntry>();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriveMode {
+    Chs,
+    Lba,
+}
 
-        core::slice::from_raw_parts(self.mmap_addr as *const MultibootMMapEntry, num_mmap_addr)
+#[derive(Debug, Clone, Copy)]
+pub struct Drive {
+    pub number: u8,
+    pub mode: DriveMode,
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors: u8,
+    ports: usize,
+    port_count: usize,
+}
+
+impl Drive {
+    /// The I/O ports the BIOS uses for this drive.
+    pub fn ports(&self) -> impl Iterator<Item = u16> {
+        let ports = self.ports as *const u16;
+        (0..self.port_count).map(move |i| unsafe { read_unaligned(ports.add(i)) })
     }
+}
 
-    pub fn loop_through_memory_map(&self, f: fn(&MultibootMMapEntry)) {
+impl fmt::Display for Drive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x}, {:?}, {} cylinders, {} heads, {} sectors, ports",
+            self.number, self.mode, self.cylinders, self.heads, self.sectors
+        )?;
+        for port in self.ports() {
+            write!(f, " {port:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Drives {
+    next: usize,
+    end: usize,
+}
+
+// size: u32, number: u8, mode: u8, cylinders: u16, heads: u8, sectors: u8, then the ports
+const DRIVE_HEADER_SIZE: usize = 10;
+
+impl Iterator for Drives {
+    type Item = Drive;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next + DRIVE_HEADER_SIZE > self.end {
+            return None;
+        }
+        let entry = self.next as *const u8;
         unsafe {
-            for mmap in self.get_mmap_addrs() {
-                f(&mmap);
+            let size = read_unaligned(entry as *const u32) as usize;
+            if size < DRIVE_HEADER_SIZE || self.next + size > self.end {
+                return None;
             }
+            self.next += size;
+            Some(Drive {
+                number: *entry.add(4),
+                mode: if *entry.add(5) == 0 {
+                    DriveMode::Chs
+                } else {
+                    DriveMode::Lba
+                },
+                cylinders: read_unaligned(entry.add(6) as *const u16),
+                heads: *entry.add(8),
+                sectors: *entry.add(9),
+                ports: entry.add(DRIVE_HEADER_SIZE) as usize,
+                port_count: (size - DRIVE_HEADER_SIZE) / size_of::<u16>(),
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ApmTable {
+    pub version: u16,
+    pub cseg: u16,
+    pub offset: u32,
+    pub cseg_16: u16,
+    pub dseg: u16,
+    pub flags: u16,
+    pub cseg_len: u16,
+    pub cseg_16_len: u16,
+    pub dseg_len: u16,
+}
+
+impl fmt::Display for ApmTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version {}.{}, flags {:#x}, entry at {:#x}:{:#x}, 32-bit code {} bytes, \
+             16-bit code at {:#x} {} bytes, data at {:#x} {} bytes",
+            self.version >> 8,
+            self.version & 0xFF,
+            self.flags,
+            self.cseg,
+            self.offset,
+            self.cseg_len,
+            self.cseg_16,
+            self.cseg_16_len,
+            self.dseg,
+            self.dseg_len
+        )
+    }
+}
+
+pub const MULTIBOOT_FRAMEBUFFER_TYPE_INDEXED: u8 = 0;
+pub const MULTIBOOT_FRAMEBUFFER_TYPE_RGB: u8 = 1;
+pub const MULTIBOOT_FRAMEBUFFER_TYPE_EGA_TEXT: u8 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FramebufferKind {
+    Indexed {
+        palette_addr: u32,
+        palette_colors: u16,
+    },
+    Rgb {
+        red_position: u8,
+        red_size: u8,
+        green_position: u8,
+        green_size: u8,
+        blue_position: u8,
+        blue_size: u8,
+    },
+    EgaText,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    /// Physical address
+    pub addr: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    pub kind: FramebufferKind,
+}
+
+impl fmt::Display for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} {}, {} bits per pixel, {} bytes per line, at {:#x}",
+            self.width, self.height, self.kind, self.bpp, self.pitch, self.addr
+        )
+    }
+}
+
+impl fmt::Display for FramebufferKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Indexed {
+                palette_addr,
+                palette_colors,
+            } => write!(f, "indexed, {palette_colors} colors at {palette_addr:#x}"),
+            Self::Rgb {
+                red_position,
+                red_size,
+                green_position,
+                green_size,
+                blue_position,
+                blue_size,
+            } => write!(
+                f,
+                "RGB, bits {red_size}@{red_position} red, {green_size}@{green_position} green, \
+                 {blue_size}@{blue_position} blue"
+            ),
+            Self::EgaText => write!(f, "EGA text"),
         }
     }
 }
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
 pub struct MultibootMMapEntry {
     size: u32,
@@ -120,6 +761,10 @@ impl MultibootMMapEntry {
     pub fn r#type(&self) -> u32 {
         self.r#type
     }
+
+    pub fn memory_type(&self) -> MultibootMemoryMappedType {
+        MultibootMemoryMappedType::from_u32(self.r#type())
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -148,5 +793,4 @@ impl MultibootMemoryMappedType {
     }
 }
 
-#[allow(unused)]
 pub const MULTIBOOT_BOOTLOADER_MAGIC: u32 = 0x2BADB002;
@@ -0,0 +1,291 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::boot::BootInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl FromStr for Ipv4Addr {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut octets = [0; 4];
+        let mut parts = s.split('.');
+        for octet in octets.iter_mut() {
+            *octet = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        }
+        if parts.next().is_some() {
+            return Err(());
+        }
+        Ok(Self(octets))
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{a}.{b}.{c}.{d}")
+    }
+}
+
+/// An address along with the length of its network prefix, like `10.0.2.15/24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    pub addr: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = s.split_once('/').ok_or(())?;
+        let prefix_len = prefix_len.parse().map_err(|_| ())?;
+        if prefix_len > 32 {
+            return Err(());
+        }
+        Ok(Self {
+            addr: addr.parse()?,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            "trace" => Ok(Self::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Where `print!` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Vga,
+    Serial,
+    Both,
+}
+
+impl Console {
+    pub fn vga(self) -> bool {
+        self != Self::Serial
+    }
+
+    pub fn serial(self) -> bool {
+        self != Self::Vga
+    }
+}
+
+impl FromStr for Console {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vga" => Ok(Self::Vga),
+            "serial" => Ok(Self::Serial),
+            "both" => Ok(Self::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Kernel configuration, read from the command line the bootloader gives us.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `ip=`: address and network of the network card
+    pub ip: Option<Ipv4Cidr>,
+    /// `gw=`: default gateway
+    pub gateway: Option<Ipv4Addr>,
+    /// `http.port=`: port the HTTP server listens on
+    pub http_port: u16,
+    /// `log=`: most verbose level that gets logged
+    pub log_level: LogLevel,
+    /// `console=`: `vga`, `serial` or `both`
+    pub console: Console,
+    /// `heap.bench=`: compare the heap fit policies at boot, needs the heap-bench feature
+    pub heap_bench: bool,
+}
+
+const DEFAULT_CONFIG: Config = Config {
+    ip: None,
+    gateway: None,
+    http_port: 80,
+    log_level: LogLevel::Info,
+    console: Console::Both,
+    heap_bench: false,
+};
+
+#[derive(Debug)]
+pub enum ConfigError<'a> {
+    UnknownKey(&'a str),
+    InvalidValue { key: &'a str, value: &'a str },
+}
+
+impl fmt::Display for ConfigError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKey(key) => write!(f, "unknown option {key}"),
+            Self::InvalidValue { key, value } => write!(f, "invalid value {value:?} for {key}"),
+        }
+    }
+}
+
+fn parse_value<'a, T: FromStr>(key: &'a str, value: &'a str) -> Result<T, ConfigError<'a>> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::InvalidValue { key, value })
+}
+
+impl Config {
+    /// Read the `key=value` options separated by spaces in `command_line`. Words without `=`,
+    /// like the kernel path GRUB puts first, are skipped. Options that can't be used are given to
+    /// `on_error` and leave the default value.
+    pub fn parse<'a>(command_line: &'a str, mut on_error: impl FnMut(ConfigError<'a>)) -> Self {
+        let mut config = DEFAULT_CONFIG;
+        for option in command_line.split_ascii_whitespace() {
+            if let Some((key, value)) = option.split_once('=') {
+                if let Err(error) = config.set(key, value) {
+                    on_error(error);
+                }
+            }
+        }
+        config
+    }
+
+    fn set<'a>(&mut self, key: &'a str, value: &'a str) -> Result<(), ConfigError<'a>> {
+        match key {
+            "ip" => self.ip = Some(parse_value(key, value)?),
+            "gw" => self.gateway = Some(parse_value(key, value)?),
+            "http.port" => match parse_value(key, value)? {
+                0 => return Err(ConfigError::InvalidValue { key, value }),
+                port => self.http_port = port,
+            },
+            "log" => self.log_level = parse_value(key, value)?,
+            "console" => self.console = parse_value(key, value)?,
+            "heap.bench" => self.heap_bench = parse_value(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key)),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ip {
+            Some(ip) => write!(f, "ip={ip}")?,
+            None => write!(f, "ip=none")?,
+        }
+        match self.gateway {
+            Some(gateway) => write!(f, " gw={gateway}")?,
+            None => write!(f, " gw=none")?,
+        }
+        write!(
+            f,
+            " http.port={} log={:?} console={:?} heap.bench={}",
+            self.http_port, self.log_level, self.console, self.heap_bench
+        )
+    }
+}
+
+static mut CONFIG: Config = DEFAULT_CONFIG;
+
+/// Read the configuration from the kernel command line. Until then the defaults are used.
+pub fn init(boot_info: &BootInfo) {
+    let command_line = boot_info.command_line().unwrap_or("");
+    let config = Config::parse(command_line, |error| {
+        log!(Warn, "Ignoring kernel option: {error}.")
+    });
+    unsafe { *core::ptr::addr_of_mut!(CONFIG) = config }
+}
+
+pub fn get() -> &'static Config {
+    unsafe { &*core::ptr::addr_of!(CONFIG) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn parse_with_errors(command_line: &str) -> (Config, Vec<ConfigError<'_>>) {
+        let mut errors = Vec::new();
+        let config = Config::parse(command_line, |error| errors.push(error));
+        (config, errors)
+    }
+
+    fn assert_defaults(config: &Config) {
+        assert_eq!(config.ip, DEFAULT_CONFIG.ip);
+        assert_eq!(config.gateway, DEFAULT_CONFIG.gateway);
+        assert_eq!(config.http_port, DEFAULT_CONFIG.http_port);
+        assert_eq!(config.log_level, DEFAULT_CONFIG.log_level);
+        assert_eq!(config.console, DEFAULT_CONFIG.console);
+        assert_eq!(config.heap_bench, DEFAULT_CONFIG.heap_bench);
+    }
+
+    #[test_case]
+    fn every_option_is_read() {
+        let (config, errors) = parse_with_errors(
+            "/boot/kernel ip=10.0.2.15/24 gw=10.0.2.2 http.port=8080 log=debug console=serial",
+        );
+        assert!(errors.is_empty());
+        assert_eq!(
+            config.ip,
+            Some(Ipv4Cidr {
+                addr: Ipv4Addr([10, 0, 2, 15]),
+                prefix_len: 24,
+            })
+        );
+        assert_eq!(config.gateway, Some(Ipv4Addr([10, 0, 2, 2])));
+        assert_eq!(config.http_port, 8080);
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.console, Console::Serial);
+    }
+
+    #[test_case]
+    fn invalid_values_keep_the_defaults() {
+        let (config, errors) = parse_with_errors(
+            "ip=10.0.2.15/33 ip=10.0.2.256/24 ip=10.0.2.15 gw=10.0.256.2 gw=10.0.2 \
+             http.port=0 http.port=70000 log=loud console=",
+        );
+        assert_defaults(&config);
+        assert_eq!(errors.len(), 9);
+        assert!(errors
+            .iter()
+            .all(|error| matches!(error, ConfigError::InvalidValue { .. })));
+    }
+
+    #[test_case]
+    fn unknown_keys_are_reported_and_bare_words_skipped() {
+        let (config, errors) = parse_with_errors("quiet foo=bar splash =x");
+        assert_defaults(&config);
+        assert!(matches!(
+            errors.as_slice(),
+            [ConfigError::UnknownKey("foo"), ConfigError::UnknownKey("")]
+        ));
+    }
+}
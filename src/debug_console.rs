@@ -0,0 +1,45 @@
+use alloc::string::String;
+
+use crate::allocator;
+use crate::interrupts;
+use crate::io::serial;
+use crate::memory::slab;
+
+/// Echo what is typed on the serial line and run it as a command once a line is complete, to
+/// look at the kernel while it runs. Sleeps between interrupts, it never returns.
+pub fn run() -> ! {
+    let mut line = String::new();
+    loop {
+        while let Some(byte) = serial::read_byte() {
+            print!("{}", byte as char);
+            if byte == b'\r' || byte == b'\n' {
+                command(line.trim());
+                line.clear();
+            } else {
+                line.push(byte as char);
+            }
+        }
+        interrupts::wait_for_interrupt();
+    }
+}
+
+fn command(command: &str) {
+    match command {
+        "" => {}
+        "heap" => {
+            print!("{}", allocator::ALLOCATOR.stats());
+        }
+        "heap blocks" => {
+            for block in allocator::ALLOCATOR.blocks() {
+                let state = if block.allocated { "used" } else { "free" };
+                println!("{:#010x} {:8} {state}", block.address, block.size);
+            }
+        }
+        "slabs" => {
+            for cache in slab::stats() {
+                println!("{cache}");
+            }
+        }
+        _ => println!("Unknown command {command:?}, try heap, heap blocks or slabs."),
+    }
+}
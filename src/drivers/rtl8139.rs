@@ -0,0 +1,428 @@
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use core::time::Duration;
+
+use crate::allocator;
+use crate::config;
+use crate::interrupts::{
+    self,
+    irq::{self, RegisterError},
+};
+use crate::io::pci::{PciDeviceHeader, PCI_COMMAND_BUS_MASTER, PCI_COMMAND_IO_SPACE};
+use crate::io::{inb, inl, inw, outb, outl, outw};
+use crate::memory::dma::DmaBuffer;
+use crate::memory::slab::{ObjectCache, SlabBox};
+use crate::time::Deadline;
+
+// https://wiki.osdev.org/RTL8139
+// http://realtek.info/pdf/rtl8139cp.pdf
+pub const VENDOR_ID: u16 = 0x10ec;
+pub const DEVICE_ID: u16 = 0x8139;
+
+const REG_IDR0: u16 = 0x00; // MAC address, 6 bytes
+const REG_TSD0: u16 = 0x10; // Transmit status of descriptor 0, 4 x u32
+const REG_TSAD0: u16 = 0x20; // Transmit start address of descriptor 0, 4 x u32
+const REG_RBSTART: u16 = 0x30; // Receive buffer start address
+const REG_CR: u16 = 0x37; // Command register
+const REG_CAPR: u16 = 0x38; // Current address of packet read
+const REG_IMR: u16 = 0x3C; // Interrupt mask register
+const REG_ISR: u16 = 0x3E; // Interrupt status register
+const REG_RCR: u16 = 0x44; // Receive configuration register
+const REG_CONFIG1: u16 = 0x52;
+
+const CR_RST: u8 = 1 << 4;
+const CR_RE: u8 = 1 << 3;
+const CR_TE: u8 = 1 << 2;
+const CR_BUFE: u8 = 1 << 0;
+
+const RCR_APM: u32 = 1 << 1; // Accept physical match packets
+const RCR_AM: u32 = 1 << 2; // Accept multicast packets
+const RCR_AB: u32 = 1 << 3; // Accept broadcast packets
+const RCR_WRAP: u32 = 1 << 7; // Let the packets overflow the ring instead of wrapping them
+
+// Same bits in IMR and ISR
+const ISR_ROK: u16 = 1 << 0;
+const ISR_RER: u16 = 1 << 1;
+const ISR_TOK: u16 = 1 << 2;
+const ISR_TER: u16 = 1 << 3;
+const ISR_RX_OVERFLOW: u16 = 1 << 4;
+
+const TSD_OWN: u32 = 1 << 13;
+const TSD_TOK: u32 = 1 << 15;
+
+const RX_STATUS_ROK: u16 = 1 << 0;
+const RX_CONFIG: u32 = RCR_APM | RCR_AM | RCR_AB | RCR_WRAP;
+
+// Every packet in the ring is prefixed by a status word and a length word
+const RX_HEADER_LEN: usize = 4;
+// 1514 bytes of frame, a VLAN tag and the CRC
+const MAX_FRAME_LEN: usize = 1522;
+const CRC_LEN: usize = 4;
+
+// RCR.RBLEN = 0 selects a 8K + 16 bytes ring. With RCR.WRAP set, the card may write a whole
+// packet past the end of the ring, so we keep room for one more maximum sized packet.
+const RX_RING_LEN: usize = 8192;
+const RX_BUFFER_SIZE: usize = RX_RING_LEN + 16 + RX_HEADER_LEN + MAX_FRAME_LEN;
+const TX_BUFFER_SIZE: usize = 1792;
+const TX_DESCRIPTORS: usize = 4;
+// The card only needs dword aligned buffers
+const BUFFER_ALIGN: usize = 16;
+
+const MIN_FRAME_SIZE: usize = 60;
+const RESET_TIMEOUT: usize = 1_000_000;
+
+// The IRQ handler only knows about one card, so only one can be driven at a time.
+static IN_USE: AtomicBool = AtomicBool::new(false);
+
+// What the IRQ handler needs to know about the card
+static IRQ_IO_BASE: AtomicU16 = AtomicU16::new(0);
+static IRQ_COUNT: AtomicU32 = AtomicU32::new(0);
+
+// Received frames are copied out of the ring into these
+static PACKETS: ObjectCache<Packet> = ObjectCache::new("rtl8139 packets", || Packet {
+    len: 0,
+    data: [0; MAX_FRAME_LEN - CRC_LEN],
+});
+
+/// A received Ethernet frame, without its CRC.
+pub struct Packet {
+    len: usize,
+    data: [u8; MAX_FRAME_LEN - CRC_LEN],
+}
+
+impl Deref for Packet {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+#[derive(Debug)]
+pub enum Rtl8139InitError {
+    NotAnRtl8139,
+    NoIoSpace,
+    ResetTimeout,
+    AlreadyInUse,
+    NoDmaMemory,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    FrameTooLarge,
+    Busy,
+}
+
+pub struct Rtl8139 {
+    io_base: u16,
+    irq: u8,
+    interrupts_enabled: bool,
+    mac: [u8; 6],
+    // The card does DMA with physical addresses, into the receive ring and out of one transmit
+    // buffer per descriptor, one after the other
+    rx_buffer: DmaBuffer,
+    tx_buffers: DmaBuffer,
+    rx_offset: usize,
+    // Frames received while there was no memory to copy them
+    rx_dropped: usize,
+    tx_current: usize,
+    tx_in_flight: [bool; TX_DESCRIPTORS],
+}
+
+impl Rtl8139 {
+    pub fn new(header: &PciDeviceHeader) -> Result<Self, Rtl8139InitError> {
+        if header.vendor_id != VENDOR_ID || header.device_id != DEVICE_ID {
+            return Err(Rtl8139InitError::NotAnRtl8139);
+        }
+
+        let bar0 = header.addr.get_bar(0);
+        if bar0 & 0x1 == 0 {
+            return Err(Rtl8139InitError::NoIoSpace);
+        }
+        let io_base = (bar0 & !0x3) as u16;
+
+        let (Some(rx_buffer), Some(tx_buffers)) = (
+            DmaBuffer::new(RX_BUFFER_SIZE, BUFFER_ALIGN),
+            DmaBuffer::new(TX_DESCRIPTORS * TX_BUFFER_SIZE, BUFFER_ALIGN),
+        ) else {
+            return Err(Rtl8139InitError::NoDmaMemory);
+        };
+
+        if IN_USE.swap(true, Ordering::AcqRel) {
+            return Err(Rtl8139InitError::AlreadyInUse);
+        }
+
+        // The card can only read and write our buffers if it is allowed to master the bus.
+        let command = header.addr.get_command();
+        header
+            .addr
+            .set_command(command | PCI_COMMAND_IO_SPACE | PCI_COMMAND_BUS_MASTER);
+
+        let mut card = Self {
+            io_base,
+            irq: header.addr.get_interrupt_line(),
+            interrupts_enabled: false,
+            mac: [0; 6],
+            rx_buffer,
+            tx_buffers,
+            rx_offset: 0,
+            rx_dropped: 0,
+            tx_current: 0,
+            tx_in_flight: [false; TX_DESCRIPTORS],
+        };
+
+        if let Err(e) = unsafe { card.reset() } {
+            IN_USE.store(false, Ordering::Release);
+            return Err(e);
+        }
+        Ok(card)
+    }
+
+    unsafe fn reset(&mut self) -> Result<(), Rtl8139InitError> {
+        // Power on
+        outb(self.io_base + REG_CONFIG1, 0x00);
+
+        // Software reset, the bit is cleared by the card once done
+        outb(self.io_base + REG_CR, CR_RST);
+        let mut timeout = RESET_TIMEOUT;
+        while inb(self.io_base + REG_CR) & CR_RST != 0 {
+            timeout -= 1;
+            if timeout == 0 {
+                return Err(Rtl8139InitError::ResetTimeout);
+            }
+        }
+
+        for (i, byte) in self.mac.iter_mut().enumerate() {
+            *byte = inb(self.io_base + REG_IDR0 + i as u16);
+        }
+
+        self.rx_buffer.fill(0);
+        // The card wants physical addresses
+        outl(
+            self.io_base + REG_RBSTART,
+            self.rx_buffer.physical_address() as u32,
+        );
+        self.rx_offset = 0;
+
+        for i in 0..TX_DESCRIPTORS {
+            outl(
+                self.io_base + REG_TSAD0 + 4 * i as u16,
+                (self.tx_buffers.physical_address() + i * TX_BUFFER_SIZE) as u32,
+            );
+        }
+        self.tx_current = 0;
+        self.tx_in_flight = [false; TX_DESCRIPTORS];
+
+        // We poll the card for now
+        outw(self.io_base + REG_IMR, 0);
+
+        outl(self.io_base + REG_RCR, RX_CONFIG);
+        outb(self.io_base + REG_CR, CR_RE | CR_TE);
+
+        Ok(())
+    }
+
+    /// Start the receive ring over, losing the packets in it. Once a packet header is garbage,
+    /// so is the offset of the packets after it.
+    unsafe fn reset_receiver(&mut self) {
+        // Like Linux does, turning the receiver off and on resets the ring pointers of the card,
+        // and the receive configuration is written again after that
+        outb(self.io_base + REG_CR, CR_TE);
+        outb(self.io_base + REG_CR, CR_RE | CR_TE);
+        outl(self.io_base + REG_RCR, RX_CONFIG);
+        outl(
+            self.io_base + REG_RBSTART,
+            self.rx_buffer.physical_address() as u32,
+        );
+        self.rx_offset = 0;
+        outw(self.io_base + REG_ISR, ISR_ROK | ISR_RER | ISR_RX_OVERFLOW);
+    }
+
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    /// Have the card raise its IRQ line on receive and transmit events.
+    pub fn enable_interrupts(&mut self) -> Result<(), RegisterError> {
+        IRQ_IO_BASE.store(self.io_base, Ordering::Release);
+        irq::register_pci_handler(self.irq, handle_irq)?;
+        unsafe {
+            outw(
+                self.io_base + REG_IMR,
+                ISR_ROK | ISR_RER | ISR_TOK | ISR_TER | ISR_RX_OVERFLOW,
+            );
+        }
+        self.interrupts_enabled = true;
+        Ok(())
+    }
+
+    /// Send a raw Ethernet frame, without the CRC which is appended by the card. Frames
+    /// shorter than the Ethernet minimum are padded with zeros.
+    pub fn send(&mut self, frame: &[u8]) -> Result<(), SendError> {
+        if frame.len() > TX_BUFFER_SIZE {
+            return Err(SendError::FrameTooLarge);
+        }
+
+        let descriptor = self.tx_current;
+        let tsd = self.io_base + REG_TSD0 + 4 * descriptor as u16;
+
+        unsafe {
+            // OWN is set by the card once it has copied the buffer to its FIFO.
+            if self.tx_in_flight[descriptor] && inl(tsd) & TSD_OWN == 0 {
+                return Err(SendError::Busy);
+            }
+
+            let start = descriptor * TX_BUFFER_SIZE;
+            let buffer = &mut self.tx_buffers[start..start + TX_BUFFER_SIZE];
+            buffer[..frame.len()].copy_from_slice(frame);
+            let len = frame.len().max(MIN_FRAME_SIZE);
+            buffer[frame.len()..len].fill(0);
+
+            // Writing the size with OWN cleared starts the transmission
+            outl(tsd, len as u32);
+        }
+
+        self.tx_in_flight[descriptor] = true;
+        self.tx_current = (descriptor + 1) % TX_DESCRIPTORS;
+        Ok(())
+    }
+
+    /// Returns whether the last frame given to `send` made it on the wire.
+    pub fn last_send_done(&self) -> bool {
+        let descriptor = (self.tx_current + TX_DESCRIPTORS - 1) % TX_DESCRIPTORS;
+        let tsd = self.io_base + REG_TSD0 + 4 * descriptor as u16;
+        unsafe { inl(tsd) & TSD_TOK != 0 }
+    }
+
+    /// Frames the card received that were dropped because there was no memory left.
+    pub fn dropped_frames(&self) -> usize {
+        self.rx_dropped
+    }
+
+    /// Pop the next received Ethernet frame from the ring, without its CRC. Frames that can't be
+    /// copied out of the ring for lack of memory are dropped, like the card does when the ring
+    /// is full. A packet with a bad header empties the ring.
+    pub fn receive(&mut self) -> Option<SlabBox<Packet>> {
+        unsafe {
+            loop {
+                if inb(self.io_base + REG_CR) & CR_BUFE != 0 {
+                    return None;
+                }
+
+                let packet = &self.rx_buffer[self.rx_offset..];
+                let status = u16::from_le_bytes([packet[0], packet[1]]);
+                let len = u16::from_le_bytes([packet[2], packet[3]]) as usize;
+
+                // The length includes the CRC
+                if status & RX_STATUS_ROK == 0 || !(CRC_LEN..=MAX_FRAME_LEN).contains(&len) {
+                    self.reset_receiver();
+                    return None;
+                }
+
+                let data = &packet[RX_HEADER_LEN..RX_HEADER_LEN + len - CRC_LEN];
+                let frame = PACKETS.alloc().map(|mut frame| {
+                    frame.len = data.len();
+                    frame.data[..data.len()].copy_from_slice(data);
+                    frame
+                });
+                if frame.is_none() {
+                    self.rx_dropped += 1;
+                }
+
+                // Packets are dword aligned in the ring. CAPR lags 16 bytes behind our offset.
+                self.rx_offset = (self.rx_offset + RX_HEADER_LEN + len + 3) & !3;
+                self.rx_offset %= RX_RING_LEN;
+                outw(
+                    self.io_base + REG_CAPR,
+                    (self.rx_offset as u16).wrapping_sub(16),
+                );
+                outw(self.io_base + REG_ISR, ISR_ROK);
+
+                if frame.is_some() {
+                    return frame;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Rtl8139 {
+    fn drop(&mut self) {
+        if self.interrupts_enabled {
+            unsafe { outw(self.io_base + REG_IMR, 0) };
+            irq::unregister_handler(self.irq);
+        }
+        // Stop the DMA before giving the buffers back
+        unsafe { outb(self.io_base + REG_CR, 0) };
+        IN_USE.store(false, Ordering::Release);
+    }
+}
+
+fn handle_irq() {
+    let io_base = IRQ_IO_BASE.load(Ordering::Acquire);
+    // The line stays asserted until every status bit is written back. Received frames are
+    // still picked up by `receive`, which looks at the ring itself.
+    unsafe {
+        let status = inw(io_base + REG_ISR);
+        outw(io_base + REG_ISR, status);
+    }
+    IRQ_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Bring the card up and put a broadcast frame on the wire.
+pub fn test(header: &PciDeviceHeader) {
+    let mut card = match Rtl8139::new(header) {
+        Ok(card) => card,
+        Err(e) => {
+            println!("rtl8139: init failed: {e:?}");
+            return;
+        }
+    };
+
+    if let Err(e) = card.enable_interrupts() {
+        println!("rtl8139: could not register IRQ {}: {e:?}", card.irq);
+    }
+
+    let mac = card.mac_address();
+    println!(
+        "rtl8139: mac {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+    let config = config::get();
+    match (config.ip, config.gateway) {
+        (Some(ip), Some(gateway)) => println!("rtl8139: ip {ip} via {gateway}"),
+        (Some(ip), None) => println!("rtl8139: ip {ip}, no gateway"),
+        (None, _) => println!("rtl8139: no ip configured"),
+    }
+
+    let Ok(mut frame) = allocator::try_with_capacity(MIN_FRAME_SIZE) else {
+        println!("rtl8139: no memory for a frame");
+        return;
+    };
+    frame.extend_from_slice(&[0xff; 6]); // broadcast
+    frame.extend_from_slice(&mac);
+    frame.extend_from_slice(&0x88b5u16.to_be_bytes()); // local experimental ethertype
+    frame.extend_from_slice(b"Hello from webservOS");
+
+    match card.send(&frame) {
+        Ok(()) => {
+            let deadline = Deadline::after(Duration::from_millis(100));
+            while !card.last_send_done() && !deadline.has_passed() {
+                interrupts::wait_for_interrupt();
+            }
+            println!("rtl8139: sent {} bytes", frame.len())
+        }
+        Err(e) => println!("rtl8139: send failed: {e:?}"),
+    }
+
+    let mut received = 0;
+    while let Some(frame) = card.receive() {
+        received += 1;
+        println!("rtl8139: received {} bytes", frame.len());
+    }
+    println!(
+        "rtl8139: {received} frames waiting in the ring, {} dropped, {} interrupts",
+        card.dropped_frames(),
+        IRQ_COUNT.load(Ordering::Relaxed)
+    );
+}
@@ -0,0 +1,233 @@
+use core::arch::asm;
+use core::mem::size_of;
+
+// https://wiki.osdev.org/Global_Descriptor_Table
+// https://wiki.osdev.org/Task_State_Segment
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+#[allow(unused)]
+pub const USER_CODE_SELECTOR: u16 = 0x18 | 3;
+#[allow(unused)]
+pub const USER_DATA_SELECTOR: u16 = 0x20 | 3;
+pub const TSS_SELECTOR: u16 = 0x28;
+pub const DOUBLE_FAULT_TSS_SELECTOR: u16 = 0x30;
+
+const GDT_ENTRIES: usize = 7;
+
+// Access bytes
+const KERNEL_CODE: u8 = 0x9A; // present, ring 0, code, readable
+const KERNEL_DATA: u8 = 0x92; // present, ring 0, data, writable
+const USER_CODE: u8 = 0xFA; // present, ring 3, code, readable
+const USER_DATA: u8 = 0xF2; // present, ring 3, data, writable
+const TSS_AVAILABLE: u8 = 0x89; // present, ring 0, 32-bit available TSS
+
+// Flags nibble: 4 KiB granularity, 32-bit protected mode segment
+const FLAT_FLAGS: u8 = 0xC;
+
+// Bit 1 of eflags is always set, everything else clear: interrupts disabled
+const EFLAGS_RESERVED: u32 = 1 << 1;
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct SegmentDescriptor {
+    limit_low: u16,
+    base_low: u16,
+    base_middle: u8,
+    access: u8,
+    flags_limit_high: u8,
+    base_high: u8,
+}
+
+impl SegmentDescriptor {
+    const fn new(base: u32, limit: u32, access: u8, flags: u8) -> Self {
+        Self {
+            limit_low: (limit & 0xFFFF) as u16,
+            base_low: (base & 0xFFFF) as u16,
+            base_middle: (base >> 16 & 0xFF) as u8,
+            access,
+            flags_limit_high: (flags << 4) | (limit >> 16 & 0xF) as u8,
+            base_high: (base >> 24 & 0xFF) as u8,
+        }
+    }
+
+    const fn null() -> Self {
+        Self::new(0, 0, 0, 0)
+    }
+
+    const fn flat(access: u8) -> Self {
+        Self::new(0, 0xFFFFF, access, FLAT_FLAGS)
+    }
+}
+
+#[repr(C, packed)]
+struct GdtDescriptor {
+    limit: u16,
+    base: u32,
+}
+
+/// Hardware task state segment. We don't use hardware task switching for scheduling, the CPU
+/// only reads `ss0:esp0` from it when an interrupt brings it from ring 3 to ring 0. The one
+/// exception is the double fault handler, which runs as its own task.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct TaskStateSegment {
+    pub link: u32,
+    pub esp0: u32,
+    pub ss0: u32,
+    pub esp1: u32,
+    pub ss1: u32,
+    pub esp2: u32,
+    pub ss2: u32,
+    pub cr3: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub eax: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub ebx: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub es: u32,
+    pub cs: u32,
+    pub ss: u32,
+    pub ds: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub ldtr: u32,
+    pub trap: u16,
+    pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        Self {
+            link: 0,
+            esp0: 0,
+            ss0: 0,
+            esp1: 0,
+            ss1: 0,
+            esp2: 0,
+            ss2: 0,
+            cr3: 0,
+            eip: 0,
+            eflags: 0,
+            eax: 0,
+            ecx: 0,
+            edx: 0,
+            ebx: 0,
+            esp: 0,
+            ebp: 0,
+            esi: 0,
+            edi: 0,
+            es: 0,
+            cs: 0,
+            ss: 0,
+            ds: 0,
+            fs: 0,
+            gs: 0,
+            ldtr: 0,
+            trap: 0,
+            // No I/O permission bitmap
+            iomap_base: size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+static mut GDT: [SegmentDescriptor; GDT_ENTRIES] = [
+    SegmentDescriptor::null(),
+    SegmentDescriptor::flat(KERNEL_CODE),
+    SegmentDescriptor::flat(KERNEL_DATA),
+    SegmentDescriptor::flat(USER_CODE),
+    SegmentDescriptor::flat(USER_DATA),
+    // The TSS descriptors need the address of the TSS, they are filled in `init`
+    SegmentDescriptor::null(),
+    SegmentDescriptor::null(),
+];
+
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+static mut DOUBLE_FAULT_TSS: TaskStateSegment = TaskStateSegment::new();
+
+extern "C" {
+    // Defined in boot.S
+    static stack_top: u8;
+}
+
+fn tss_descriptor(tss: *const TaskStateSegment) -> SegmentDescriptor {
+    SegmentDescriptor::new(
+        tss as u32,
+        (size_of::<TaskStateSegment>() - 1) as u32,
+        TSS_AVAILABLE,
+        0,
+    )
+}
+
+/// Load our GDT, reload every segment register from it and load the task register.
+pub fn init() {
+    unsafe {
+        let tss = core::ptr::addr_of_mut!(TSS);
+        (*tss).ss0 = KERNEL_DATA_SELECTOR as u32;
+        (*tss).esp0 = core::ptr::addr_of!(stack_top) as u32;
+
+        let gdt = core::ptr::addr_of_mut!(GDT);
+        (*gdt)[(TSS_SELECTOR >> 3) as usize] = tss_descriptor(tss);
+        (*gdt)[(DOUBLE_FAULT_TSS_SELECTOR >> 3) as usize] =
+            tss_descriptor(core::ptr::addr_of!(DOUBLE_FAULT_TSS));
+
+        let descriptor = GdtDescriptor {
+            limit: (size_of::<[SegmentDescriptor; GDT_ENTRIES]>() - 1) as u16,
+            base: gdt as u32,
+        };
+
+        // cs can't be moved to, a far return pops it along with the new eip.
+        asm!(r#"
+            .att_syntax
+            lgdt (%eax)
+            mov %dx, %ds
+            mov %dx, %es
+            mov %dx, %fs
+            mov %dx, %gs
+            mov %dx, %ss
+            push %ecx
+            push $2f
+            lret
+        2:
+            ltr %di
+            "#,
+            in("eax") &descriptor as *const GdtDescriptor,
+            in("ecx") KERNEL_CODE_SELECTOR as u32,
+            in("dx") KERNEL_DATA_SELECTOR,
+            in("di") TSS_SELECTOR);
+    }
+}
+
+/// Set up the task the CPU switches to on a double fault: it starts at `entry` with the stack
+/// pointer at `stack`, in the current address space.
+pub fn init_double_fault_task(entry: u32, stack: u32) {
+    unsafe {
+        let cr3: u32;
+        asm!(r#"
+            .att_syntax
+            mov %cr3, %eax
+            "#,
+            out("eax") cr3);
+
+        let tss = core::ptr::addr_of_mut!(DOUBLE_FAULT_TSS);
+        (*tss).cr3 = cr3;
+        (*tss).eip = entry;
+        (*tss).eflags = EFLAGS_RESERVED;
+        (*tss).esp = stack;
+        (*tss).cs = KERNEL_CODE_SELECTOR as u32;
+        (*tss).ss = KERNEL_DATA_SELECTOR as u32;
+        (*tss).ds = KERNEL_DATA_SELECTOR as u32;
+        (*tss).es = KERNEL_DATA_SELECTOR as u32;
+        (*tss).fs = KERNEL_DATA_SELECTOR as u32;
+        (*tss).gs = KERNEL_DATA_SELECTOR as u32;
+    }
+}
+
+/// The state the kernel was in when the CPU switched to another task, saved in its TSS.
+pub fn interrupted_task() -> TaskStateSegment {
+    unsafe { *core::ptr::addr_of!(TSS) }
+}
@@ -0,0 +1,273 @@
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::arch::x86::__cpuid;
+use core::ptr::{read_volatile, write_volatile};
+
+use super::IRQ_BASE;
+use crate::acpi::{self, InterruptSourceOverride};
+use crate::memory::paging::{self, PageFlags};
+
+// https://wiki.osdev.org/APIC
+// https://wiki.osdev.org/IOAPIC
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const CPUID_FEATURES_EDX_APIC: u32 = 1 << 9;
+const IA32_APIC_BASE_MSR: u32 = 0x1B;
+const APIC_BASE_ENABLE: u32 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u32 = 0xFFFF_F000;
+
+// Local APIC registers
+const LAPIC_ID: u32 = 0x20;
+const LAPIC_TPR: u32 = 0x80;
+const LAPIC_EOI: u32 = 0xB0;
+const LAPIC_SVR: u32 = 0xF0;
+const LAPIC_LVT_LINT0: u32 = 0x350;
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_DELIVERY_EXTINT: u32 = 0b111 << 8;
+
+// I/O APIC registers
+const IOAPIC_REGSEL: u32 = 0x00;
+const IOAPIC_WIN: u32 = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u32 = 1 << 15;
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+// Interrupt source override flags, as in the MPS specification
+const POLARITY_MASK: u16 = 0b11;
+const POLARITY_ACTIVE_HIGH: u16 = 0b01;
+const POLARITY_ACTIVE_LOW: u16 = 0b11;
+const TRIGGER_MASK: u16 = 0b11 << 2;
+const TRIGGER_EDGE: u16 = 0b01 << 2;
+const TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+#[derive(Debug)]
+pub enum ApicInitError {
+    NotSupported,
+    NoMadt,
+    NoIoApic,
+    #[allow(dead_code)]
+    Unmappable(paging::MapError),
+}
+
+// Registers must not be cached. Returns where they are mapped.
+unsafe fn map_registers(address: u32) -> Result<u32, ApicInitError> {
+    paging::map_physical(
+        address as usize,
+        paging::PAGE_SIZE,
+        PageFlags::WRITABLE | PageFlags::CACHE_DISABLE,
+    )
+    .map(|virt| virt as u32)
+    .map_err(ApicInitError::Unmappable)
+}
+
+struct IoApic {
+    address: u32,
+    gsi_base: u32,
+    redirections: u32,
+}
+
+impl IoApic {
+    unsafe fn read(&self, register: u32) -> u32 {
+        write_volatile((self.address + IOAPIC_REGSEL) as *mut u32, register);
+        read_volatile((self.address + IOAPIC_WIN) as *const u32)
+    }
+
+    unsafe fn write(&self, register: u32, value: u32) {
+        write_volatile((self.address + IOAPIC_REGSEL) as *mut u32, register);
+        write_volatile((self.address + IOAPIC_WIN) as *mut u32, value);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.redirections
+    }
+
+    unsafe fn set_redirection(&self, gsi: u32, low: u32, high: u32) {
+        let register = IOAPIC_REDIRECTION_TABLE + 2 * (gsi - self.gsi_base);
+        // Mask the entry while it is half written
+        self.write(register, REDIRECTION_MASKED);
+        self.write(register + 1, high);
+        self.write(register, low);
+    }
+}
+
+struct Apic {
+    local_base: u32,
+    local_id: u8,
+    io_apics: Vec<IoApic>,
+    overrides: Vec<InterruptSourceOverride>,
+}
+
+impl Apic {
+    unsafe fn read_local(&self, register: u32) -> u32 {
+        read_volatile((self.local_base + register) as *const u32)
+    }
+
+    unsafe fn write_local(&self, register: u32, value: u32) {
+        write_volatile((self.local_base + register) as *mut u32, value)
+    }
+
+    /// Global system interrupt and redirection flags of a legacy IRQ line, None if the I/O APIC
+    /// input of the line isn't known.
+    ///
+    /// Which I/O APIC input a PCI device is wired to is only told by the _PRT methods of the ACPI
+    /// namespace, and we can't run them. The line in the configuration space of the device is a
+    /// PIC line, only known to be an I/O APIC input when the MADT overrides it, like QEMU does
+    /// for its PCI interrupt links. Other PCI lines stay on the PICs.
+    fn route(&self, irq: u8, pci: bool) -> Option<(u32, u32)> {
+        let source_override = self.overrides.iter().find(|o| o.source == irq);
+        if pci && source_override.is_none() {
+            return None;
+        }
+
+        // ISA interrupts are edge triggered and active high, PCI ones level triggered and active
+        // low, unless the firmware tells otherwise.
+        let (mut gsi, mut flags) = if pci {
+            (irq as u32, POLARITY_ACTIVE_LOW | TRIGGER_LEVEL)
+        } else {
+            (irq as u32, POLARITY_ACTIVE_HIGH | TRIGGER_EDGE)
+        };
+        if let Some(o) = source_override {
+            gsi = o.gsi;
+            if o.flags & POLARITY_MASK != 0 {
+                flags = (flags & !POLARITY_MASK) | (o.flags & POLARITY_MASK);
+            }
+            if o.flags & TRIGGER_MASK != 0 {
+                flags = (flags & !TRIGGER_MASK) | (o.flags & TRIGGER_MASK);
+            }
+        }
+
+        let mut redirection = 0;
+        if flags & POLARITY_MASK == POLARITY_ACTIVE_LOW {
+            redirection |= REDIRECTION_ACTIVE_LOW;
+        }
+        if flags & TRIGGER_MASK == TRIGGER_LEVEL {
+            redirection |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+        Some((gsi, redirection))
+    }
+
+    fn io_apic_for(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics.iter().find(|io_apic| io_apic.handles(gsi))
+    }
+}
+
+static mut APIC: Option<Apic> = None;
+
+unsafe fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high);
+    (high as u64) << 32 | low as u64
+}
+
+unsafe fn write_msr(msr: u32, value: u64) {
+    asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32
+    );
+}
+
+pub fn is_supported() -> bool {
+    let features = unsafe { __cpuid(1) };
+    features.edx & CPUID_FEATURES_EDX_APIC != 0
+}
+
+/// Enable the local APIC of this CPU and mask every I/O APIC input. Legacy IRQs are then routed
+/// one by one through `unmask_irq`.
+pub fn init() -> Result<(), ApicInitError> {
+    if !is_supported() {
+        return Err(ApicInitError::NotSupported);
+    }
+    let madt = acpi::parse_madt().ok_or(ApicInitError::NoMadt)?;
+    if madt.io_apics.is_empty() {
+        return Err(ApicInitError::NoIoApic);
+    }
+
+    unsafe {
+        let base = read_msr(IA32_APIC_BASE_MSR) as u32;
+        let local_base = if madt.local_apic_address != 0 {
+            madt.local_apic_address
+        } else {
+            base & APIC_BASE_ADDRESS_MASK
+        };
+        write_msr(
+            IA32_APIC_BASE_MSR,
+            ((local_base & APIC_BASE_ADDRESS_MASK)
+                | (base & !APIC_BASE_ADDRESS_MASK)
+                | APIC_BASE_ENABLE) as u64,
+        );
+
+        let mut apic = Apic {
+            local_base: map_registers(local_base)?,
+            local_id: 0,
+            io_apics: Vec::new(),
+            overrides: madt.overrides,
+        };
+
+        apic.write_local(LAPIC_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+        apic.write_local(LAPIC_TPR, 0);
+        // The PICs stay wired to LINT0, for the lines the I/O APIC can't route
+        apic.write_local(LAPIC_LVT_LINT0, LVT_DELIVERY_EXTINT);
+        apic.local_id = (apic.read_local(LAPIC_ID) >> 24) as u8;
+
+        for entry in madt.io_apics {
+            let mut io_apic = IoApic {
+                address: map_registers(entry.address)?,
+                gsi_base: entry.gsi_base,
+                redirections: 0,
+            };
+            io_apic.redirections = (io_apic.read(IOAPIC_VERSION) >> 16 & 0xFF) + 1;
+            for i in 0..io_apic.redirections {
+                io_apic.write(IOAPIC_REDIRECTION_TABLE + 2 * i, REDIRECTION_MASKED);
+            }
+            apic.io_apics.push(io_apic);
+        }
+
+        *core::ptr::addr_of_mut!(APIC) = Some(apic);
+    }
+    Ok(())
+}
+
+fn apic() -> &'static Apic {
+    unsafe {
+        (*core::ptr::addr_of!(APIC))
+            .as_ref()
+            .expect("The APIC is initialized")
+    }
+}
+
+/// Deliver the legacy IRQ `irq` to this CPU, on the same vector the PIC would use. Returns false
+/// if the I/O APIC can't, see `Apic::route`.
+pub fn unmask_irq(irq: u8, pci: bool) -> bool {
+    let apic = apic();
+    let Some((gsi, flags)) = apic.route(irq, pci) else {
+        return false;
+    };
+    let Some(io_apic) = apic.io_apic_for(gsi) else {
+        return false;
+    };
+    let vector = (IRQ_BASE + irq) as u32;
+    unsafe { io_apic.set_redirection(gsi, vector | flags, (apic.local_id as u32) << 24) };
+    true
+}
+
+pub fn mask_irq(irq: u8) {
+    let apic = apic();
+    let Some((gsi, _)) = apic.route(irq, false) else {
+        return;
+    };
+    if let Some(io_apic) = apic.io_apic_for(gsi) {
+        unsafe {
+            let register = IOAPIC_REDIRECTION_TABLE + 2 * (gsi - io_apic.gsi_base);
+            io_apic.write(register, io_apic.read(register) | REDIRECTION_MASKED);
+        }
+    }
+}
+
+pub fn end_of_interrupt() {
+    unsafe { apic().write_local(LAPIC_EOI, 0) }
+}
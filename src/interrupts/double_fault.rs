@@ -0,0 +1,97 @@
+use super::{idt, Eflags};
+use crate::gdt;
+use crate::memory::paging::{self, PAGE_SIZE};
+
+// https://wiki.osdev.org/Double_Fault#Double_Fault
+const DOUBLE_FAULT: u8 = 8;
+
+const STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+// An access a bit below the stack pointer can hit the guard page while the stack pointer itself
+// is still above it.
+const OVERFLOW_MARGIN: u32 = 128;
+
+/// A kernel stack with a page below it that is left unmapped.
+#[repr(C, align(4096))]
+struct GuardedStack {
+    guard: [u8; PAGE_SIZE],
+    stack: [u8; STACK_SIZE],
+}
+
+// The double fault task gets its own stack, the kernel one may be what caused the fault.
+static mut DOUBLE_FAULT_STACK: GuardedStack = GuardedStack {
+    guard: [0; PAGE_SIZE],
+    stack: [0; STACK_SIZE],
+};
+
+extern "C" {
+    // Defined in boot.S
+    static stack_guard: u8;
+    static stack_bottom: u8;
+
+    // Defined in stubs.S
+    static double_fault_task_entry: u8;
+}
+
+/// Guard page and bottom of every kernel stack.
+fn kernel_stacks() -> [(u32, u32); 2] {
+    unsafe {
+        let double_fault_stack = core::ptr::addr_of!(DOUBLE_FAULT_STACK);
+        [
+            (
+                core::ptr::addr_of!(stack_guard) as u32,
+                core::ptr::addr_of!(stack_bottom) as u32,
+            ),
+            (
+                core::ptr::addr_of!((*double_fault_stack).guard) as u32,
+                core::ptr::addr_of!((*double_fault_stack).stack) as u32,
+            ),
+        ]
+    }
+}
+
+pub fn init() {
+    unsafe {
+        for (guard, _) in kernel_stacks() {
+            paging::unmap(guard as usize).expect("The kernel is mapped");
+        }
+
+        let (_, bottom) = kernel_stacks()[1];
+        gdt::init_double_fault_task(
+            core::ptr::addr_of!(double_fault_task_entry) as u32,
+            bottom + STACK_SIZE as u32,
+        );
+        idt::set_task_gate(DOUBLE_FAULT, gdt::DOUBLE_FAULT_TSS_SELECTOR);
+    }
+}
+
+#[no_mangle]
+extern "C" fn double_fault_task() -> ! {
+    let task = gdt::interrupted_task();
+    for (guard, bottom) in kernel_stacks() {
+        if task.esp >= guard && task.esp < bottom + OVERFLOW_MARGIN {
+            panic!(
+                "Kernel stack overflow at {:#010x} (ESP={:08x})",
+                task.eip, task.esp
+            );
+        }
+    }
+
+    emergency_println!("EXCEPTION: Double Fault");
+    emergency_println!(
+        "EAX={:08x} EBX={:08x} ECX={:08x} EDX={:08x}",
+        task.eax,
+        task.ebx,
+        task.ecx,
+        task.edx
+    );
+    emergency_println!(
+        "ESI={:08x} EDI={:08x} EBP={:08x} ESP={:08x}",
+        task.esi,
+        task.edi,
+        task.ebp,
+        task.esp
+    );
+    emergency_println!("EIP={:08x} EFLAGS={}", task.eip, Eflags(task.eflags));
+    panic!("Unhandled Double Fault exception at {:#010x}", task.eip);
+}
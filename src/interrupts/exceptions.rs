@@ -0,0 +1,130 @@
+use core::arch::asm;
+use core::fmt;
+
+use super::InterruptFrame;
+
+// https://wiki.osdev.org/Exceptions
+const EXCEPTION_NAMES: [&str; 32] = [
+    "Divide Error",
+    "Debug",
+    "Non-maskable Interrupt",
+    "Breakpoint",
+    "Overflow",
+    "Bound Range Exceeded",
+    "Invalid Opcode",
+    "Device Not Available",
+    "Double Fault",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS",
+    "Segment Not Present",
+    "Stack-Segment Fault",
+    "General Protection Fault",
+    "Page Fault",
+    "Reserved",
+    "x87 Floating-Point Exception",
+    "Alignment Check",
+    "Machine Check",
+    "SIMD Floating-Point Exception",
+    "Virtualization Exception",
+    "Control Protection Exception",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection Exception",
+    "VMM Communication Exception",
+    "Security Exception",
+    "Reserved",
+];
+
+const PAGE_FAULT: u32 = 14;
+
+// Page fault error code bits
+const PAGE_FAULT_PRESENT: u32 = 1 << 0;
+const PAGE_FAULT_WRITE: u32 = 1 << 1;
+const PAGE_FAULT_USER: u32 = 1 << 2;
+const PAGE_FAULT_RESERVED_BIT: u32 = 1 << 3;
+const PAGE_FAULT_INSTRUCTION_FETCH: u32 = 1 << 4;
+
+/// What a page fault error code says about the access that faulted.
+struct PageFaultCause(u32);
+
+impl fmt::Display for PageFaultCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = if self.0 & PAGE_FAULT_INSTRUCTION_FETCH != 0 {
+            "instruction fetch"
+        } else if self.0 & PAGE_FAULT_WRITE != 0 {
+            "write"
+        } else {
+            "read"
+        };
+        let reason = if self.0 & PAGE_FAULT_PRESENT != 0 {
+            "protection violation"
+        } else {
+            "page not present"
+        };
+        let mode = if self.0 & PAGE_FAULT_USER != 0 {
+            "user"
+        } else {
+            "kernel"
+        };
+        write!(f, "{mode} {access}, {reason}")?;
+        if self.0 & PAGE_FAULT_RESERVED_BIT != 0 {
+            write!(f, ", reserved bit set")?;
+        }
+        Ok(())
+    }
+}
+
+struct ControlRegisters {
+    cr0: u32,
+    cr2: u32,
+    cr3: u32,
+}
+
+fn read_control_registers() -> ControlRegisters {
+    let (cr0, cr2, cr3);
+    unsafe {
+        asm!(r#"
+            .att_syntax
+            mov %cr0, %eax
+            mov %cr2, %ecx
+            mov %cr3, %edx
+            "#,
+            out("eax") cr0,
+            out("ecx") cr2,
+            out("edx") cr3);
+    }
+    ControlRegisters { cr0, cr2, cr3 }
+}
+
+pub fn handle(frame: &InterruptFrame) {
+    let name = EXCEPTION_NAMES[frame.vector as usize];
+    let control = read_control_registers();
+
+    emergency_println!(
+        "EXCEPTION: {} (vector {}, error code {:#x})",
+        name,
+        frame.vector,
+        frame.error_code
+    );
+    emergency_println!("{frame}");
+    emergency_println!(
+        "CR0={:08x} CR2={:08x} CR3={:08x}",
+        control.cr0,
+        control.cr2,
+        control.cr3
+    );
+
+    if frame.vector == PAGE_FAULT {
+        panic!(
+            "Page fault on {:#010x} ({}) at {:#010x}",
+            control.cr2,
+            PageFaultCause(frame.error_code),
+            frame.eip
+        );
+    }
+    panic!("Unhandled {} exception at {:#010x}", name, frame.eip);
+}
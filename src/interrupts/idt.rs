@@ -0,0 +1,96 @@
+use core::arch::asm;
+use core::mem::size_of;
+
+use crate::gdt::KERNEL_CODE_SELECTOR;
+
+// https://wiki.osdev.org/Interrupt_Descriptor_Table
+const IDT_ENTRIES: usize = 256;
+
+// Present, ring 0, 32-bit interrupt gate (interrupts are disabled on entry)
+pub const INTERRUPT_GATE: u8 = 0x8E;
+
+// Present, ring 0, task gate
+const TASK_GATE: u8 = 0x85;
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct GateDescriptor {
+    offset_low: u16,
+    selector: u16,
+    zero: u8,
+    type_attributes: u8,
+    offset_high: u16,
+}
+
+impl GateDescriptor {
+    const fn missing() -> Self {
+        Self {
+            offset_low: 0,
+            selector: 0,
+            zero: 0,
+            type_attributes: 0,
+            offset_high: 0,
+        }
+    }
+}
+
+#[repr(C, packed)]
+struct IdtDescriptor {
+    limit: u16,
+    base: u32,
+}
+
+static mut IDT: [GateDescriptor; IDT_ENTRIES] = [GateDescriptor::missing(); IDT_ENTRIES];
+
+extern "C" {
+    // Defined in stubs.S, 0 for the vectors without a stub
+    static isr_stub_table: [u32; IDT_ENTRIES];
+}
+
+/// Point the gate of `vector` at `handler`.
+///
+/// # Safety
+/// `handler` must be the address of an interrupt entry stub that ends with `iret`.
+pub unsafe fn set_gate(vector: u8, handler: u32, type_attributes: u8) {
+    let idt = core::ptr::addr_of_mut!(IDT);
+    (*idt)[vector as usize] = GateDescriptor {
+        offset_low: (handler & 0xFFFF) as u16,
+        selector: KERNEL_CODE_SELECTOR,
+        zero: 0,
+        type_attributes,
+        offset_high: (handler >> 16) as u16,
+    };
+}
+
+/// Make `vector` switch to the task whose TSS descriptor is `tss_selector` in the GDT.
+pub unsafe fn set_task_gate(vector: u8, tss_selector: u16) {
+    let idt = core::ptr::addr_of_mut!(IDT);
+    (*idt)[vector as usize] = GateDescriptor {
+        offset_low: 0,
+        selector: tss_selector,
+        zero: 0,
+        type_attributes: TASK_GATE,
+        offset_high: 0,
+    };
+}
+
+pub fn init() {
+    unsafe {
+        let stubs = &*core::ptr::addr_of!(isr_stub_table);
+        for (vector, stub) in stubs.iter().enumerate() {
+            if *stub != 0 {
+                set_gate(vector as u8, *stub, INTERRUPT_GATE);
+            }
+        }
+
+        let descriptor = IdtDescriptor {
+            limit: (size_of::<[GateDescriptor; IDT_ENTRIES]>() - 1) as u16,
+            base: core::ptr::addr_of!(IDT) as u32,
+        };
+        asm!(r#"
+            .att_syntax
+            lidt (%eax)
+            "#,
+            in("eax") &descriptor as *const IdtDescriptor);
+    }
+}
@@ -0,0 +1,135 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{apic, pic, without_interrupts, InterruptFrame, FALLBACK_PIC_BASE, IRQ_BASE};
+
+pub const IRQ_LINES: usize = 16;
+
+pub type IrqHandler = fn();
+
+static mut HANDLERS: [Option<IrqHandler>; IRQ_LINES] = [None; IRQ_LINES];
+
+// Lines that come from PCI devices, they don't have the ISA trigger mode and polarity
+static mut PCI_LINES: u16 = 0;
+
+// Lines left on the PICs once the APIC took over, because the I/O APIC can't route them
+static mut PIC_LINES: u16 = 0;
+
+static USE_APIC: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum RegisterError {
+    InvalidLine,
+    AlreadyRegistered,
+}
+
+/// Install `handler` for the ISA interrupt line `irq` and unmask it.
+pub fn register_handler(irq: u8, handler: IrqHandler) -> Result<(), RegisterError> {
+    register(irq, handler, false)
+}
+
+/// Install `handler` for `irq`, the interrupt line read from a PCI device configuration space.
+pub fn register_pci_handler(irq: u8, handler: IrqHandler) -> Result<(), RegisterError> {
+    register(irq, handler, true)
+}
+
+fn register(irq: u8, handler: IrqHandler, pci: bool) -> Result<(), RegisterError> {
+    if irq as usize >= IRQ_LINES {
+        return Err(RegisterError::InvalidLine);
+    }
+
+    without_interrupts(|| unsafe {
+        let handlers = &mut *core::ptr::addr_of_mut!(HANDLERS);
+        if handlers[irq as usize].is_some() {
+            return Err(RegisterError::AlreadyRegistered);
+        }
+        handlers[irq as usize] = Some(handler);
+        if pci {
+            *core::ptr::addr_of_mut!(PCI_LINES) |= 1 << irq;
+        } else {
+            *core::ptr::addr_of_mut!(PCI_LINES) &= !(1 << irq);
+        }
+        unmask(irq);
+        Ok(())
+    })
+}
+
+pub fn unregister_handler(irq: u8) {
+    if irq as usize >= IRQ_LINES {
+        return;
+    }
+
+    without_interrupts(|| unsafe {
+        mask(irq);
+        (*core::ptr::addr_of_mut!(HANDLERS))[irq as usize] = None;
+    })
+}
+
+// Whether `irq` is delivered by the I/O APIC rather than the PICs
+fn on_apic(irq: u8) -> bool {
+    USE_APIC.load(Ordering::Acquire) && unsafe { *core::ptr::addr_of!(PIC_LINES) } & 1 << irq == 0
+}
+
+pub fn mask(irq: u8) {
+    if on_apic(irq) {
+        apic::mask_irq(irq);
+    } else {
+        pic::mask(irq);
+    }
+}
+
+/// Let `irq` through, from the I/O APIC once it took over, or from the PICs for the lines it
+/// can't route.
+pub fn unmask(irq: u8) {
+    if USE_APIC.load(Ordering::Acquire) {
+        let pci = unsafe { *core::ptr::addr_of!(PCI_LINES) } & 1 << irq != 0;
+        let routed = apic::unmask_irq(irq, pci);
+        unsafe {
+            if routed {
+                *core::ptr::addr_of_mut!(PIC_LINES) &= !(1 << irq);
+                return;
+            }
+            *core::ptr::addr_of_mut!(PIC_LINES) |= 1 << irq;
+        }
+    }
+    pic::unmask(irq);
+}
+
+/// Route the IRQ lines through the APIC from now on. Handlers that were already registered
+/// keep working.
+pub(super) fn switch_to_apic() {
+    without_interrupts(|| {
+        USE_APIC.store(true, Ordering::Release);
+        let handlers = unsafe { &*core::ptr::addr_of!(HANDLERS) };
+        for (irq, handler) in handlers.iter().enumerate() {
+            if handler.is_some() {
+                unmask(irq as u8);
+            }
+        }
+    })
+}
+
+pub fn handle(frame: &InterruptFrame) {
+    let vector = frame.vector as u8;
+    let (irq, from_apic) = if vector >= FALLBACK_PIC_BASE {
+        (vector - FALLBACK_PIC_BASE, false)
+    } else {
+        (vector - IRQ_BASE, USE_APIC.load(Ordering::Acquire))
+    };
+
+    if !from_apic && pic::is_spurious(irq) {
+        pic::end_of_spurious_interrupt(irq);
+        return;
+    }
+
+    // Interrupt gates keep interrupts disabled while we run, so nobody can touch HANDLERS.
+    let handler = unsafe { (*core::ptr::addr_of!(HANDLERS))[irq as usize] };
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    if from_apic {
+        apic::end_of_interrupt();
+    } else {
+        pic::end_of_interrupt(irq);
+    }
+}
@@ -0,0 +1,184 @@
+use core::arch::{asm, global_asm};
+use core::fmt;
+
+pub mod apic;
+mod double_fault;
+mod exceptions;
+pub mod idt;
+pub mod irq;
+mod pic;
+
+/// Vector of the first hardware IRQ, right after the 32 CPU exceptions
+pub const IRQ_BASE: u8 = 32;
+
+/// Where the PICs are moved once the APIC takes over, so their vectors don't mix with the I/O
+/// APIC ones. They only keep the lines the I/O APIC can't route, see `irq::unmask`, and can
+/// still raise spurious interrupts.
+const FALLBACK_PIC_BASE: u8 = 0xE0;
+
+// Entry stubs for every vector, see stubs.S
+global_asm!(include_str!("stubs.S"));
+
+/// The state saved on the stack by `interrupt_common` in stubs.S, in the order it lays it out.
+#[repr(C)]
+#[derive(Debug)]
+pub struct InterruptFrame {
+    pub gs: u32,
+    pub fs: u32,
+    pub es: u32,
+    pub ds: u32,
+
+    // pusha
+    pub edi: u32,
+    pub esi: u32,
+    pub ebp: u32,
+    pub esp_at_pusha: u32,
+    pub ebx: u32,
+    pub edx: u32,
+    pub ecx: u32,
+    pub eax: u32,
+
+    pub vector: u32,
+    pub error_code: u32,
+
+    // Pushed by the CPU
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+}
+
+impl InterruptFrame {
+    /// The stack pointer of the interrupted code, when it was running in ring 0.
+    pub fn interrupted_esp(&self) -> u32 {
+        // vector, error code, eip, cs and eflags were pushed before pusha saved esp
+        self.esp_at_pusha + 5 * 4
+    }
+}
+
+const EFLAGS_BITS: [(u32, &str); 14] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+    (14, "NT"),
+    (16, "RF"),
+    (17, "VM"),
+    (18, "AC"),
+    (21, "ID"),
+];
+
+pub struct Eflags(pub u32);
+
+impl fmt::Display for Eflags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x} [", self.0)?;
+        for (bit, name) in EFLAGS_BITS {
+            if self.0 >> bit & 1 == 1 {
+                write!(f, " {name}")?;
+            }
+        }
+        write!(f, " IOPL={} ]", self.0 >> 12 & 0x3)
+    }
+}
+
+impl fmt::Display for InterruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "EAX={:08x} EBX={:08x} ECX={:08x} EDX={:08x}",
+            self.eax, self.ebx, self.ecx, self.edx
+        )?;
+        writeln!(
+            f,
+            "ESI={:08x} EDI={:08x} EBP={:08x} ESP={:08x}",
+            self.esi,
+            self.edi,
+            self.ebp,
+            self.interrupted_esp()
+        )?;
+        writeln!(
+            f,
+            "EIP={:08x} CS={:04x} DS={:04x} ES={:04x} FS={:04x} GS={:04x}",
+            self.eip, self.cs, self.ds, self.es, self.fs, self.gs
+        )?;
+        write!(f, "EFLAGS={}", Eflags(self.eflags))
+    }
+}
+
+pub fn init() {
+    idt::init();
+    pic::remap(IRQ_BASE, IRQ_BASE + 8);
+}
+
+/// Handle double faults as a separate task with its own stack, and put guard pages under the
+/// kernel stacks so overflowing them ends in a double fault. Paging must be set up.
+pub fn init_double_fault() {
+    double_fault::init();
+}
+
+/// Switch from the 8259 PICs to the local and I/O APICs. On error the PICs stay in charge, and
+/// they keep the PCI lines the I/O APIC can't route anyway, see `apic::unmask_irq`.
+pub fn init_apic() -> Result<(), apic::ApicInitError> {
+    without_interrupts(|| {
+        apic::init()?;
+        pic::remap(FALLBACK_PIC_BASE, FALLBACK_PIC_BASE + 8);
+        irq::switch_to_apic();
+        Ok(())
+    })
+}
+
+pub fn enable() {
+    unsafe { asm!("sti") }
+}
+
+pub fn disable() {
+    unsafe { asm!("cli") }
+}
+
+pub fn are_enabled() -> bool {
+    let eflags: u32;
+    unsafe {
+        asm!(r#"
+            .att_syntax
+            pushf
+            pop %eax
+            "#,
+            out("eax") eflags);
+    }
+    eflags & (1 << 9) != 0
+}
+
+/// Halt the CPU until the next interrupt.
+pub fn wait_for_interrupt() {
+    unsafe { asm!("hlt") }
+}
+
+/// Run `f` with interrupts disabled, restoring the previous state afterward.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let were_enabled = are_enabled();
+    if were_enabled {
+        disable();
+    }
+    let res = f();
+    if were_enabled {
+        enable();
+    }
+    res
+}
+
+#[no_mangle]
+extern "C" fn interrupt_dispatch(frame: &mut InterruptFrame) {
+    match frame.vector {
+        0..=31 => exceptions::handle(frame),
+        // From the I/O APIC or the PICs, then from the PICs moved aside by `init_apic`
+        32..=47 | 0xE0..=0xEF => irq::handle(frame),
+        // Spurious interrupt of the local APIC, it must not be acknowledged
+        0xFF => {}
+        vector => panic!("Interrupt on unexpected vector {vector}"),
+    }
+}
@@ -0,0 +1,104 @@
+use crate::io::{inb, outb};
+
+// https://wiki.osdev.org/8259_PIC
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xA0;
+const SLAVE_DATA: u16 = 0xA1;
+
+const ICW1_ICW4: u8 = 0x01; // ICW4 will be present
+const ICW1_INIT: u8 = 0x10;
+const ICW4_8086: u8 = 0x01;
+
+const OCW3_READ_ISR: u8 = 0x0B;
+const EOI: u8 = 0x20;
+
+// The slave PIC is wired on the IRQ 2 line of the master
+const CASCADE_IRQ: u8 = 2;
+
+unsafe fn io_wait() {
+    // Port 0x80 is used for POST codes, writing to it is harmless and takes long enough for
+    // the PICs to catch up on old machines.
+    outb(0x80, 0);
+}
+
+/// Move the PIC vectors to `master_offset..master_offset + 8` and
+/// `slave_offset..slave_offset + 8`, out of the CPU exception range, and mask every line.
+pub fn remap(master_offset: u8, slave_offset: u8) {
+    unsafe {
+        outb(MASTER_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(SLAVE_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(MASTER_DATA, master_offset);
+        io_wait();
+        outb(SLAVE_DATA, slave_offset);
+        io_wait();
+        outb(MASTER_DATA, 1 << CASCADE_IRQ);
+        io_wait();
+        outb(SLAVE_DATA, CASCADE_IRQ);
+        io_wait();
+        outb(MASTER_DATA, ICW4_8086);
+        io_wait();
+        outb(SLAVE_DATA, ICW4_8086);
+        io_wait();
+
+        // Lines get unmasked when a handler is registered
+        outb(MASTER_DATA, 0xFF);
+        outb(SLAVE_DATA, 0xFF);
+    }
+}
+
+fn data_port(irq: u8) -> (u16, u8) {
+    if irq < 8 {
+        (MASTER_DATA, irq)
+    } else {
+        (SLAVE_DATA, irq - 8)
+    }
+}
+
+pub fn mask(irq: u8) {
+    let (port, line) = data_port(irq);
+    unsafe { outb(port, inb(port) | 1 << line) }
+}
+
+pub fn unmask(irq: u8) {
+    let (port, line) = data_port(irq);
+    unsafe { outb(port, inb(port) & !(1 << line)) }
+    if irq >= 8 {
+        unmask(CASCADE_IRQ);
+    }
+}
+
+fn in_service_register(command_port: u16) -> u8 {
+    unsafe {
+        outb(command_port, OCW3_READ_ISR);
+        inb(command_port)
+    }
+}
+
+/// IRQ 7 and IRQ 15 fire spuriously when a line is deasserted before the PIC delivers it. In
+/// that case the in service bit is not set and no EOI must be sent to the PIC that raised it.
+pub fn is_spurious(irq: u8) -> bool {
+    match irq {
+        7 => in_service_register(MASTER_COMMAND) & 0x80 == 0,
+        15 => in_service_register(SLAVE_COMMAND) & 0x80 == 0,
+        _ => false,
+    }
+}
+
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(SLAVE_COMMAND, EOI);
+        }
+        outb(MASTER_COMMAND, EOI);
+    }
+}
+
+/// EOI for a spurious IRQ 15: the master did see a real interrupt on the cascade line.
+pub fn end_of_spurious_interrupt(irq: u8) {
+    if irq == 15 {
+        unsafe { outb(MASTER_COMMAND, EOI) }
+    }
+}
@@ -0,0 +1,32 @@
+use core::fmt::Write;
+
+use super::serial::PORT;
+use super::vga::VGA_WRITER;
+use super::{inb, outb};
+
+// Stop waiting for the UART after this many polls, it may not even be initialized yet
+const SERIAL_TIMEOUT: usize = 100_000;
+
+/// Writes straight to the serial port and to VGA memory, without locking the writers `print!`
+/// uses. A crash may happen while they are in use, its report must get out anyway. What was
+/// being printed can end up mixed with it.
+pub struct EmergencyWriter;
+
+impl Write for EmergencyWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        unsafe {
+            for c in s.as_bytes() {
+                for _ in 0..SERIAL_TIMEOUT {
+                    if inb(PORT + 5) & 0x20 != 0 {
+                        break;
+                    }
+                }
+                outb(PORT, *c);
+            }
+
+            // Whoever holds the writer won't get to use it again
+            (*VGA_WRITER.force_get()).write(s.as_bytes());
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use core::arch::asm;
 
+pub mod emergency;
 pub mod serial;
 pub mod vga;
 
@@ -9,11 +10,47 @@ macro_rules! print {
         #[allow(unused_unsafe)]
         unsafe{
             use core::fmt::Write as FmtWrite;
-            let mut writer = $crate::io::vga::VGA_WRITER.borrow_mut();
-            write!(writer, $($arg)*).expect("Failed to print to vga");
+            let console = $crate::config::get().console;
+            if console.vga() {
+                let mut writer = $crate::io::vga::VGA_WRITER.lock();
+                write!(writer, $($arg)*).expect("Failed to print to vga");
+            }
 
-            let mut writer = $crate::io::serial::SerialWriter::new($crate::io::serial::PORT);
-            write!(writer, $($arg)*).expect("Failed to print to serial");
+            if console.serial() {
+                let mut writer = $crate::io::serial::SERIAL_WRITER.lock();
+                write!(writer, $($arg)*).expect("Failed to print to serial");
+            }
+        }
+    }
+}
+
+/// `print!` for the panic and exception handlers, it never fails nor waits on the consoles.
+#[macro_export]
+macro_rules! emergency_print {
+    ($($arg:tt)*) => {
+        {
+            use core::fmt::Write as FmtWrite;
+            let _ = write!($crate::io::emergency::EmergencyWriter, $($arg)*);
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! emergency_println {
+    ($($arg:tt)*) => {
+        {
+            emergency_print!($($arg)*);
+            emergency_print!("\n");
+        }
+    }
+}
+
+/// `println!` if `$level` is at most as verbose as the `log=` kernel option.
+#[macro_export]
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {
+        if $crate::config::LogLevel::$level <= $crate::config::get().log_level {
+            println!($($arg)*);
         }
     }
 }
@@ -30,7 +67,7 @@ macro_rules! println {
 
 pub mod pci;
 
-unsafe fn outb(address: u16, value: u8) {
+pub(crate) unsafe fn outb(address: u16, value: u8) {
     asm!(r#"
         .att_syntax
          out %al, %dx
@@ -40,7 +77,7 @@ unsafe fn outb(address: u16, value: u8) {
     );
 }
 
-unsafe fn inb(address: u16) -> u8 {
+pub(crate) unsafe fn inb(address: u16) -> u8 {
     let mut ret;
     asm!(r#"
         .att_syntax
@@ -51,7 +88,28 @@ unsafe fn inb(address: u16) -> u8 {
     ret
 }
 
-unsafe fn outl(address: u16, value: u32) {
+pub(crate) unsafe fn outw(address: u16, value: u16) {
+    asm!(r#"
+        .att_syntax
+         out %ax, %dx
+         "#,
+        in("ax") value,
+        in("dx") address
+    );
+}
+
+pub(crate) unsafe fn inw(address: u16) -> u16 {
+    let mut ret;
+    asm!(r#"
+        .att_syntax
+        in %dx, %ax
+        "#,
+        in("dx") address,
+        out("ax") ret);
+    ret
+}
+
+pub(crate) unsafe fn outl(address: u16, value: u32) {
     asm!(r#"
         .att_syntax
          out %eax, %dx
@@ -61,7 +119,7 @@ unsafe fn outl(address: u16, value: u32) {
     );
 }
 
-unsafe fn inl(address: u16) -> u32 {
+pub(crate) unsafe fn inl(address: u16) -> u32 {
     let mut ret;
     asm!(r#"
         .att_syntax
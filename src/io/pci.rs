@@ -23,7 +23,7 @@ impl GetByte for u16 {
 }
 
 #[derive(Debug)]
-struct PciAddr {
+pub struct PciAddr {
     bus: u8,
     slot: u8,
     function: u8,
@@ -55,37 +55,79 @@ impl PciAddr {
             (pci_config_read_word(self.bus, self.slot, self.function, 0xC + 0x2) & 0x0F) as u8
         }
     }
+
+    pub fn get_command(&self) -> u16 {
+        unsafe { pci_config_read_word(self.bus, self.slot, self.function, 0x4) }
+    }
+
+    pub fn set_command(&self, command: u16) {
+        // The status register shares the dword with the command register, its bits are
+        // write-1-to-clear so we leave them at zero.
+        unsafe { pci_config_write_dword(self.bus, self.slot, self.function, 0x4, command as u32) }
+    }
+
+    /// Read the raw value of the base address register `n` (0 to 5).
+    pub fn get_bar(&self, n: u8) -> u32 {
+        unsafe { pci_config_read_dword(self.bus, self.slot, self.function, 0x10 + 4 * n) }
+    }
+
+    /// The legacy IRQ line the firmware routed the device interrupt pin to.
+    pub fn get_interrupt_line(&self) -> u8 {
+        unsafe { pci_config_read_word(self.bus, self.slot, self.function, 0x3C).nth_byte(0) }
+    }
 }
 
+// Bits of the command register
+pub const PCI_COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const PCI_COMMAND_BUS_MASTER: u16 = 1 << 2;
+
 #[derive(Debug)]
 #[allow(unused)]
-struct PciDeviceHeader {
-    device_id: u16,
-    vendor_id: u16,
-    class: u8,
-    subclass: u8,
-    addr: PciAddr,
+pub struct PciDeviceHeader {
+    pub device_id: u16,
+    pub vendor_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub addr: PciAddr,
 }
 
-unsafe fn pci_config_read_word(bus: u8, device: u8, func: u8, offset: u8) -> u16 {
+fn pci_config_address(bus: u8, device: u8, func: u8, offset: u8) -> u32 {
     let lbus = bus as u32;
     let lslot = device as u32;
     let lfunc = func as u32;
 
     // Create configuration address as per Figure 1
-    let address = ((lbus << 16)
-        | (lslot << 11)
-        | (lfunc << 8)
-        | (offset & 0xFC) as u32
-        | (0x80000000 as u32)) as u32;
+    ((lbus << 16) | (lslot << 11) | (lfunc << 8) | (offset & 0xFC) as u32 | (0x80000000 as u32))
+        as u32
+}
 
+unsafe fn pci_config_read_word(bus: u8, device: u8, func: u8, offset: u8) -> u16 {
     // Write out the address
-    outl(CONFIG_ADDRESS, address);
+    outl(
+        CONFIG_ADDRESS,
+        pci_config_address(bus, device, func, offset),
+    );
     // Read in the data
     // (offset & 2) * 8) = 0 will choose the first word of the 32-bit register
     ((inl(CONFIG_DATA) >> ((offset & 2) * 8)) & 0xFFFF) as u16
 }
 
+unsafe fn pci_config_read_dword(bus: u8, device: u8, func: u8, offset: u8) -> u32 {
+    outl(
+        CONFIG_ADDRESS,
+        pci_config_address(bus, device, func, offset),
+    );
+    inl(CONFIG_DATA)
+}
+
+unsafe fn pci_config_write_dword(bus: u8, device: u8, func: u8, offset: u8, value: u32) {
+    outl(
+        CONFIG_ADDRESS,
+        pci_config_address(bus, device, func, offset),
+    );
+    outl(CONFIG_DATA, value);
+}
+
 fn check_function(bus: u8, device: u8, function: u8) -> Vec<PciDeviceHeader> {
     let addr = PciAddr {
         bus,
@@ -152,7 +194,7 @@ fn check_bus(bus: u8) -> Vec<PciDeviceHeader> {
     res
 }
 
-fn check_all_buses_smart() -> Vec<PciDeviceHeader> {
+pub fn check_all_buses_smart() -> Vec<PciDeviceHeader> {
     let addr = PciAddr {
         bus: 0,
         slot: 0,
@@ -179,11 +221,3 @@ fn check_all_buses_smart() -> Vec<PciDeviceHeader> {
         res
     }
 }
-
-    let pci_devices_headers = check_all_buses_smart();
-    let rtl8139 = pci_devices_headers
-        .iter()
-        .find(|e| e.device_id == 0x8139 && e.vendor_id == 0x10ec)
-        .expect("good qemu config");
-
-    println!("network card: {rtl8139:#1x?}");
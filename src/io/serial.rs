@@ -1,13 +1,40 @@
+use core::cell::UnsafeCell;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use super::{inb, outb};
+use crate::interrupts::irq;
+use crate::sync::IrqSafeMutex;
 
-pub static PORT: u16 = 0x3f8; // COM1
+pub const PORT: u16 = 0x3f8; // COM1
+pub const IRQ: u8 = 4; // COM1
+
+const INPUT_BUFFER_SIZE: usize = 256;
+
+struct InputBuffer {
+    data: UnsafeCell<[u8; INPUT_BUFFER_SIZE]>,
+    // Next byte written by the IRQ handler
+    head: AtomicUsize,
+    // Next byte read by `read_byte`
+    tail: AtomicUsize,
+}
+
+// There is a single producer, the IRQ handler, and a single consumer. Each side only writes
+// the slots the other one has published through head and tail.
+unsafe impl Sync for InputBuffer {}
+
+static INPUT: InputBuffer = InputBuffer {
+    data: UnsafeCell::new([0; INPUT_BUFFER_SIZE]),
+    head: AtomicUsize::new(0),
+    tail: AtomicUsize::new(0),
+};
 
 pub struct SerialWriter {
     port: u16,
 }
 
+pub static SERIAL_WRITER: IrqSafeMutex<SerialWriter> = IrqSafeMutex::new(SerialWriter::new(PORT));
+
 pub struct SerialWriterInitError;
 
 pub fn init() -> Result<(), SerialWriterInitError> {
@@ -30,12 +57,44 @@ pub fn init() -> Result<(), SerialWriterInitError> {
         // If serial is not faulty set it in normal operation mode
         // (not-loopback with IRQs enabled and OUT#1 and OUT#2 bits enabled)
         outb(PORT + 4, 0x0F);
+        outb(PORT + 1, 0x01); // Interrupt when data is received
     }
+    let _ = irq::register_handler(IRQ, handle_irq);
     Ok(())
 }
 
+fn handle_irq() {
+    unsafe {
+        // Drain the FIFO, as long as the line status says data is ready
+        while inb(PORT + 5) & 0x01 != 0 {
+            let byte = inb(PORT);
+            let head = INPUT.head.load(Ordering::Relaxed);
+            let next = (head + 1) % INPUT_BUFFER_SIZE;
+            if next == INPUT.tail.load(Ordering::Acquire) {
+                // Full, drop the byte
+                continue;
+            }
+            (*INPUT.data.get())[head] = byte;
+            INPUT.head.store(next, Ordering::Release);
+        }
+    }
+}
+
+/// Pop a byte received on the serial line, if any.
+pub fn read_byte() -> Option<u8> {
+    let tail = INPUT.tail.load(Ordering::Relaxed);
+    if tail == INPUT.head.load(Ordering::Acquire) {
+        return None;
+    }
+    let byte = unsafe { (*INPUT.data.get())[tail] };
+    INPUT
+        .tail
+        .store((tail + 1) % INPUT_BUFFER_SIZE, Ordering::Release);
+    Some(byte)
+}
+
 impl SerialWriter {
-    pub fn new(port: u16) -> Self {
+    pub const fn new(port: u16) -> Self {
         Self { port }
     }
 
@@ -1,35 +1,9 @@
-use core::{cell::RefCell, fmt::Write};
+use core::fmt::Write;
 
-pub static VGA_WRITER: StaticVGATerminalWriter = StaticVGATerminalWriter::new();
+use crate::sync::IrqSafeMutex;
 
-pub struct StaticVGATerminalWriter {
-    inner: RefCell<VGATerminalWriter>,
-}
-
-impl core::ops::Deref for StaticVGATerminalWriter {
-    type Target = RefCell<VGATerminalWriter>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.inner
-    }
-}
-
-// I only have one thread
-unsafe impl Sync for StaticVGATerminalWriter {}
-
-impl StaticVGATerminalWriter {
-    const fn new() -> StaticVGATerminalWriter {
-        StaticVGATerminalWriter {
-            inner: RefCell::new(VGATerminalWriter::new()),
-        }
-    }
-}
-
-impl Write for StaticVGATerminalWriter {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        write!(self.inner.borrow_mut(), "{s}")
-    }
-}
+pub static VGA_WRITER: IrqSafeMutex<VGATerminalWriter> =
+    IrqSafeMutex::new(VGATerminalWriter::new());
 
 #[allow(dead_code)]
 #[repr(u8)]
@@ -70,7 +44,8 @@ pub struct VGATerminalWriter {
     buffer: *mut u16,
 }
 
-unsafe impl Sync for VGATerminalWriter {}
+// The buffer is the VGA memory, which isn't tied to whoever is writing to it
+unsafe impl Send for VGATerminalWriter {}
 
 impl Write for VGATerminalWriter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
@@ -80,7 +55,7 @@ impl Write for VGATerminalWriter {
 }
 
 pub fn init() {
-    let vtw = VGA_WRITER.borrow_mut();
+    let vtw = VGA_WRITER.lock();
     for y in 0..VGA_HEIGHT {
         for x in 0..VGA_WIDTH {
             let index = y * VGA_WIDTH + x;
@@ -99,7 +74,7 @@ impl VGATerminalWriter {
             VgaColor::VgaColorLightGrey as u8,
             VgaColor::VgaColorBlack as u8,
         );
-        let buffer = 0xB8000 as *mut u16;
+        let buffer = crate::memory::phys_to_virt(0xB8000) as *mut u16;
         Self {
             row,
             column,
@@ -2,23 +2,41 @@
 #![feature(const_mut_refs)]
 #![feature(panic_info_message)]
 #![feature(const_for)]
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::runner)]
+#![reexport_test_harness_main = "test_main"]
 #![allow(bad_asm_style)]
 #![no_std] // don't link the Rust standard library
 #![no_main] // disable all Rust-level entry points
 
 use alloc::vec;
 
+use crate::io::pci::check_all_buses_smart;
 use crate::io::serial;
 use crate::io::vga;
-use core::arch::asm;
 use core::arch::global_asm;
 use core::panic::PanicInfo;
 
 #[macro_use]
 mod io;
+mod acpi;
 mod allocator;
+mod backtrace;
+#[cfg(feature = "heap-bench")]
+mod bench;
 mod boot;
+mod config;
+mod debug_console;
 mod drivers;
+mod gdt;
+mod interrupts;
+mod memory;
+mod multiboot2;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod time;
 
 extern crate alloc;
 // Include boot.s which defines _start as inline assembly in main. This allows us to do more fine
@@ -32,15 +50,59 @@ extern "C" {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn kernel_main(
-    multiboot_infos: &'static boot::MultibootInfo,
-    _multiboot_magic: u32,
-) -> ! {
+pub unsafe extern "C" fn kernel_main(multiboot_infos: usize, multiboot_magic: u32) -> ! {
+    gdt::init();
     vga::init();
+    interrupts::init();
     let _ = serial::init();
-    unsafe { allocator::ALLOCATOR.init(multiboot_infos) }
+    let boot_info = boot::BootInfo::new(multiboot_magic, multiboot_infos)
+        .expect("Booted by a multiboot bootloader");
+    config::init(&boot_info);
+    memory::frame::init(&boot_info);
+    memory::paging::init();
+    backtrace::init(&boot_info);
+    interrupts::init_double_fault();
+    if let Some(rsdp) = boot_info.rsdp() {
+        acpi::set_rsdp(rsdp);
+    }
+    unsafe { allocator::ALLOCATOR.init() }
+    match interrupts::init_apic() {
+        Ok(()) => println!("Interrupts routed through the APIC."),
+        Err(e) => println!("No APIC ({e:?}), interrupts routed through the PIC."),
+    }
+    time::init();
+    interrupts::enable();
+
+    #[cfg(test)]
+    test_main();
 
     println!("Boot working.");
+    if let Some(name) = boot_info.boot_loader_name() {
+        println!("    Booted by {name}");
+    }
+    if let Some(command_line) = boot_info.command_line() {
+        println!("    Command line: {command_line}");
+    }
+    if let Some(framebuffer) = boot_info.framebuffer() {
+        println!("    Video mode: {framebuffer}");
+    }
+    if let boot::BootInfo::Multiboot(info) = boot_info {
+        for module in info.modules().unwrap_or(&[]) {
+            println!(
+                "    Module at {:#x}..{:#x}: {}",
+                module.start(),
+                module.end(),
+                module.string().unwrap_or("")
+            );
+        }
+    }
+    for drive in boot_info.drives().into_iter().flatten() {
+        log!(Debug, "    BIOS drive {drive}");
+    }
+    if let Some(apm) = boot_info.apm_table() {
+        log!(Debug, "    APM {apm}");
+    }
+    log!(Debug, "    Configuration: {}", config::get());
     println!("Allocator working:");
     let v = vec![1, 2, 3, 4];
     println!("    A vector: {v:?}");
@@ -48,40 +110,69 @@ pub unsafe extern "C" fn kernel_main(
     m.insert("bonjour", 7);
     m.insert("salut", 5);
     println!("    A map: {m:?}");
+    let heap = allocator::ALLOCATOR.stats();
+    println!(
+        "    {} bytes in use out of {} KiB, {}% fragmented",
+        heap.bytes_in_use,
+        heap.size / 1024,
+        heap.fragmentation()
+    );
+    if config::get().heap_bench {
+        #[cfg(feature = "heap-bench")]
+        bench::heap();
+        #[cfg(not(feature = "heap-bench"))]
+        println!("    heap.bench needs a kernel built with the heap-bench feature.");
+    }
+    println!(
+        "Physical memory: {} KiB free out of {} KiB.",
+        memory::frame::free_count() * memory::frame::FRAME_SIZE / 1024,
+        memory::frame::total_count() * memory::frame::FRAME_SIZE / 1024
+    );
 
     let pci_devices_headers = check_all_buses_smart();
     let rtl8139 = pci_devices_headers
         .iter()
-        .find(|e| e.device_id == 0x8139 && e.vendor_id == 0x10ec)
+        .find(|e| {
+            e.device_id == drivers::rtl8139::DEVICE_ID && e.vendor_id == drivers::rtl8139::VENDOR_ID
+        })
         .expect("good qemu config");
 
     println!("network card: {rtl8139:#1x?}");
 
-    // drivers::rtl8139::test(rtl8139);
+    drivers::rtl8139::test(rtl8139);
+
+    println!("Up for {:?}.", time::uptime());
+    println!("Date: {}", time::now().http_date());
 
-    loop {}
+    debug_console::run()
 }
 
 // This function is called on panic.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("Pannic'ed on:");
+    emergency_println!("Pannic'ed on:");
     if let Some(message) = info.message() {
-        print!("{}", message);
+        emergency_print!("{}", message);
     }
     if let Some(location) = info.location() {
-        print!(
+        emergency_print!(
             " at {}:{}:{}",
             location.file(),
             location.line(),
             location.column()
         );
     }
-    println!(".");
-    unsafe {
-        asm! { "hlt" }
+    emergency_println!(".");
+    backtrace::print();
+    #[cfg(test)]
+    testing::exit_qemu(testing::ExitCode::Failed);
+    #[cfg(not(test))]
+    {
+        unsafe {
+            core::arch::asm! { "hlt" }
+        }
+        loop {}
     }
-    loop {}
 }
 
 #[no_mangle]
@@ -0,0 +1,71 @@
+use core::ops::{Deref, DerefMut};
+
+use super::frame::{self, Frame, FRAME_SIZE};
+use super::phys_to_virt;
+
+/// Memory that devices read and write on their own, given to them by its physical address.
+///
+/// It is physically contiguous, zeroed when allocated and given back when dropped. It comes
+/// from the frame allocator, so it is in memory the bootloader said is available and in the
+/// direct map, far below 4 GiB: any 32-bit address register can hold its address.
+pub struct DmaBuffer {
+    virt: usize,
+    phys: usize,
+    len: usize,
+    frames: usize,
+}
+
+#[allow(dead_code)]
+impl DmaBuffer {
+    /// `len` bytes with a physical address that is a multiple of `align`, which must be a power of
+    /// two. None if there is no such memory left.
+    pub fn new(len: usize, align: usize) -> Option<Self> {
+        assert!(
+            align.is_power_of_two(),
+            "DMA alignment must be a power of two"
+        );
+        // Frames are aligned on their size already
+        let frames = len.max(1).div_ceil(FRAME_SIZE);
+        let first = frame::allocate_contiguous_aligned(frames, align.div_ceil(FRAME_SIZE))?;
+        let phys = first.start_address();
+        let virt = phys_to_virt(phys);
+        // Whatever the frames held before must not reach a device
+        unsafe { core::ptr::write_bytes(virt as *mut u8, 0, frames * FRAME_SIZE) }
+        Some(Self {
+            virt,
+            phys,
+            len,
+            frames,
+        })
+    }
+
+    /// The address to give to the device.
+    pub fn physical_address(&self) -> usize {
+        self.phys
+    }
+
+    pub fn virtual_address(&self) -> usize {
+        self.virt
+    }
+}
+
+impl Deref for DmaBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.virt as *const u8, self.len) }
+    }
+}
+
+impl DerefMut for DmaBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt as *mut u8, self.len) }
+    }
+}
+
+// The device must be done with the buffer before it is dropped
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        frame::deallocate_contiguous(Frame::containing(self.phys), self.frames);
+    }
+}
@@ -0,0 +1,156 @@
+use super::DIRECT_MAP_SIZE;
+use crate::boot::BootInfo;
+use crate::interrupts;
+
+// https://wiki.osdev.org/Page_Frame_Allocation
+pub const FRAME_SIZE: usize = 4096;
+
+// Every frame the direct map covers
+const FRAME_COUNT: usize = DIRECT_MAP_SIZE / FRAME_SIZE;
+
+// Legacy VGA memory and BIOS ROMs, whatever the memory map says about them
+const VGA_BIOS_START: usize = 0xA0000;
+const VGA_BIOS_END: usize = 0x100000;
+
+/// A 4 KiB page of physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Frame(usize);
+
+#[allow(dead_code)]
+impl Frame {
+    /// The frame in which the physical address `addr` is.
+    pub fn containing(addr: usize) -> Self {
+        Self(addr / FRAME_SIZE)
+    }
+
+    pub fn start_address(&self) -> usize {
+        self.0 * FRAME_SIZE
+    }
+
+    pub fn number(&self) -> usize {
+        self.0
+    }
+}
+
+// One bit per frame, set when the frame is free. Everything starts used, so the bitmap lives
+// in .bss and only the memory the bootloader said is available gets freed.
+static mut BITMAP: [u32; FRAME_COUNT / 32] = [0; FRAME_COUNT / 32];
+static mut FREE_FRAMES: usize = 0;
+static mut TOTAL_FRAMES: usize = 0;
+static mut LAST_USABLE: usize = 0;
+
+// Where to start looking for a free frame, every frame below it is used.
+static mut NEXT_FREE: usize = 0;
+
+unsafe fn is_free(frame: usize) -> bool {
+    (*core::ptr::addr_of!(BITMAP))[frame / 32] >> (frame % 32) & 1 == 1
+}
+
+unsafe fn mark_free(frame: usize) {
+    if !is_free(frame) {
+        (*core::ptr::addr_of_mut!(BITMAP))[frame / 32] |= 1 << (frame % 32);
+        *core::ptr::addr_of_mut!(FREE_FRAMES) += 1;
+        let next_free = core::ptr::addr_of_mut!(NEXT_FREE);
+        *next_free = (*next_free).min(frame);
+    }
+}
+
+unsafe fn mark_used(frame: usize) {
+    if is_free(frame) {
+        (*core::ptr::addr_of_mut!(BITMAP))[frame / 32] &= !(1 << (frame % 32));
+        *core::ptr::addr_of_mut!(FREE_FRAMES) -= 1;
+    }
+}
+
+/// Hand every frame of available memory that the kernel, the multiboot structures and the
+/// modules don't use to the frame allocator.
+pub fn init(boot_info: &BootInfo) {
+    interrupts::without_interrupts(|| unsafe {
+        *core::ptr::addr_of_mut!(NEXT_FREE) = FRAME_COUNT;
+        boot_info.loop_through_usable_memory(|start, end| {
+            // Only the frames that are entirely usable, and that the kernel can reach through the
+            // direct map
+            let end = end.min(DIRECT_MAP_SIZE as u64);
+            let first = start.div_ceil(FRAME_SIZE as u64) as usize;
+            let last = (end / FRAME_SIZE as u64) as usize;
+            for frame in first..last {
+                mark_free(frame);
+            }
+            let last_usable = core::ptr::addr_of_mut!(LAST_USABLE);
+            if last > first {
+                *last_usable = (*last_usable).max(last - 1);
+            }
+        });
+        for frame in VGA_BIOS_START / FRAME_SIZE..VGA_BIOS_END / FRAME_SIZE {
+            mark_used(frame);
+        }
+        *core::ptr::addr_of_mut!(TOTAL_FRAMES) = *core::ptr::addr_of!(FREE_FRAMES);
+    });
+}
+
+/// Take one free frame.
+pub fn allocate() -> Option<Frame> {
+    allocate_contiguous(1)
+}
+
+/// Take `count` free frames that follow each other in physical memory, returns the first.
+pub fn allocate_contiguous(count: usize) -> Option<Frame> {
+    allocate_contiguous_aligned(count, 1)
+}
+
+/// Take `count` free frames that follow each other in physical memory, the first one's number
+/// being a multiple of `align`, which must be a power of two. Returns the first.
+pub fn allocate_contiguous_aligned(count: usize, align: usize) -> Option<Frame> {
+    if count == 0 {
+        return None;
+    }
+    interrupts::without_interrupts(|| unsafe {
+        let mut first = (*core::ptr::addr_of!(NEXT_FREE)).next_multiple_of(align);
+        while first + count <= FRAME_COUNT {
+            match (first..first + count).find(|&frame| !is_free(frame)) {
+                // Restart the search after the used frame
+                Some(used) => first = (used + 1).next_multiple_of(align),
+                None => {
+                    for frame in first..first + count {
+                        mark_used(frame);
+                    }
+                    let next_free = core::ptr::addr_of_mut!(NEXT_FREE);
+                    if *next_free == first {
+                        *next_free = first + count;
+                    }
+                    return Some(Frame(first));
+                }
+            }
+        }
+        None
+    })
+}
+
+/// Give back a frame taken with `allocate`.
+pub fn deallocate(frame: Frame) {
+    deallocate_contiguous(frame, 1)
+}
+
+/// Give back `count` frames taken with `allocate_contiguous` or `allocate_contiguous_aligned`.
+pub fn deallocate_contiguous(frame: Frame, count: usize) {
+    interrupts::without_interrupts(|| unsafe {
+        for frame in frame.0..frame.0 + count {
+            mark_free(frame);
+        }
+    })
+}
+
+/// Number of frames that can still be allocated.
+pub fn free_count() -> usize {
+    interrupts::without_interrupts(|| unsafe { *core::ptr::addr_of!(FREE_FRAMES) })
+}
+
+/// The highest frame the allocator was given at boot, every frame of RAM is at or below it.
+pub fn last_usable() -> Frame {
+    Frame(unsafe { *core::ptr::addr_of!(LAST_USABLE) })
+}
+
+/// Number of frames the allocator was given at boot.
+pub fn total_count() -> usize {
+    unsafe { *core::ptr::addr_of!(TOTAL_FRAMES) }
+}
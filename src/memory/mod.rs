@@ -0,0 +1,22 @@
+pub mod dma;
+pub mod frame;
+pub mod paging;
+pub mod slab;
+
+/// Where the kernel lives in virtual memory. Physical memory is mapped from there on, the lower
+/// half is left for user processes. Must match linker.ld and boot.S.
+pub const KERNEL_OFFSET: usize = 0xC000_0000;
+
+/// How much physical memory is mapped at `KERNEL_OFFSET`. The frame allocator only hands out
+/// frames in it, the rest of the higher half is for MMIO mappings.
+pub const DIRECT_MAP_SIZE: usize = 0x3000_0000;
+
+/// Virtual address of the physical address `phys` in the direct map.
+pub const fn phys_to_virt(phys: usize) -> usize {
+    phys + KERNEL_OFFSET
+}
+
+/// Physical address of `virt`, which must be in the kernel image or in the direct map.
+pub const fn virt_to_phys(virt: usize) -> usize {
+    virt - KERNEL_OFFSET
+}
@@ -0,0 +1,214 @@
+use core::arch::asm;
+use core::ops::BitOr;
+
+use super::frame::{self, FRAME_SIZE};
+use super::{phys_to_virt, virt_to_phys, DIRECT_MAP_SIZE, KERNEL_OFFSET};
+use crate::interrupts;
+
+// https://wiki.osdev.org/Paging
+pub const PAGE_SIZE: usize = FRAME_SIZE;
+
+const ENTRIES: usize = 1024;
+const ADDRESS_MASK: u32 = 0xFFFF_F000;
+
+const CR0_WRITE_PROTECT: u32 = 1 << 16;
+
+// Physical memory that isn't in the direct map, like MMIO registers, is mapped here
+const MMIO_START: usize = KERNEL_OFFSET + DIRECT_MAP_SIZE;
+const MMIO_END: usize = 0xFFC0_0000;
+
+/// Flags of a page table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags(u32);
+
+#[allow(dead_code)]
+impl PageFlags {
+    pub const PRESENT: Self = Self(1 << 0);
+    pub const WRITABLE: Self = Self(1 << 1);
+    pub const USER: Self = Self(1 << 2);
+    pub const WRITE_THROUGH: Self = Self(1 << 3);
+    pub const CACHE_DISABLE: Self = Self(1 << 4);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PageFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    NotAligned,
+    AlreadyMapped,
+    OutOfMemory,
+}
+
+#[repr(C, align(4096))]
+struct PageTable([u32; ENTRIES]);
+
+static mut PAGE_DIRECTORY: PageTable = PageTable([0; ENTRIES]);
+
+// Next free virtual address of the MMIO area, it is never given back
+static mut NEXT_MMIO: usize = MMIO_START;
+
+fn is_aligned(addr: usize) -> bool {
+    addr & (PAGE_SIZE - 1) == 0
+}
+
+// Page tables are in frames of RAM, which are in the direct map
+unsafe fn table_at(phys: u32) -> *mut PageTable {
+    phys_to_virt(phys as usize) as *mut PageTable
+}
+
+unsafe fn directory_entry(virt: usize) -> *mut u32 {
+    core::ptr::addr_of_mut!(PAGE_DIRECTORY.0[virt >> 22])
+}
+
+/// The page table entry of `virt`, if it has a page table.
+unsafe fn page_entry(virt: usize) -> Option<*mut u32> {
+    let pde = *directory_entry(virt);
+    if pde & PageFlags::PRESENT.0 == 0 {
+        return None;
+    }
+    let table = table_at(pde & ADDRESS_MASK);
+    Some(core::ptr::addr_of_mut!(
+        (*table).0[virt >> 12 & (ENTRIES - 1)]
+    ))
+}
+
+/// The page table entry of `virt`, creating its page table if needed.
+unsafe fn page_entry_or_create(virt: usize, flags: PageFlags) -> Result<*mut u32, MapError> {
+    let pde = directory_entry(virt);
+    if *pde & PageFlags::PRESENT.0 == 0 {
+        let table = frame::allocate().ok_or(MapError::OutOfMemory)?;
+        core::ptr::write_bytes(phys_to_virt(table.start_address()) as *mut u8, 0, PAGE_SIZE);
+        *pde = table.start_address() as u32 | (PageFlags::PRESENT | PageFlags::WRITABLE).0;
+    }
+    // The directory entry must allow what any of its pages allows, the page entries restrict it
+    if flags.contains(PageFlags::USER) {
+        *pde |= PageFlags::USER.0;
+    }
+    Ok(page_entry(virt).expect("The page table was just created"))
+}
+
+unsafe fn invalidate(virt: usize) {
+    asm!(r#"
+        .att_syntax
+        invlpg (%eax)
+        "#,
+        in("eax") virt);
+}
+
+/// Map the page at `virt` to the frame at `phys`. Both must be page aligned.
+pub unsafe fn map(virt: usize, phys: usize, flags: PageFlags) -> Result<(), MapError> {
+    if !is_aligned(virt) || !is_aligned(phys) {
+        return Err(MapError::NotAligned);
+    }
+    interrupts::without_interrupts(|| {
+        let entry = page_entry_or_create(virt, flags)?;
+        if *entry & PageFlags::PRESENT.0 != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+        *entry = phys as u32 | (flags | PageFlags::PRESENT).0;
+        invalidate(virt);
+        Ok(())
+    })
+}
+
+/// Remove the mapping of the page at `virt`, returns the physical address it was mapped to.
+#[allow(dead_code)]
+pub unsafe fn unmap(virt: usize) -> Option<usize> {
+    interrupts::without_interrupts(|| {
+        let entry = page_entry(virt)?;
+        if *entry & PageFlags::PRESENT.0 == 0 {
+            return None;
+        }
+        let phys = (*entry & ADDRESS_MASK) as usize;
+        *entry = 0;
+        invalidate(virt);
+        Some(phys)
+    })
+}
+
+/// The physical address `virt` is mapped to.
+#[allow(dead_code)]
+pub fn translate(virt: usize) -> Option<usize> {
+    interrupts::without_interrupts(|| unsafe {
+        let entry = *page_entry(virt)?;
+        if entry & PageFlags::PRESENT.0 == 0 {
+            return None;
+        }
+        Some((entry & ADDRESS_MASK) as usize | virt & (PAGE_SIZE - 1))
+    })
+}
+
+// Map the physical memory from `start` to `end` in the direct map. Pages that already are are
+// left as they are.
+unsafe fn map_direct(start: usize, end: usize, flags: PageFlags) -> Result<(), MapError> {
+    let first = start & !(PAGE_SIZE - 1);
+    for page in (first..end).step_by(PAGE_SIZE) {
+        match translate(phys_to_virt(page)) {
+            Some(phys) if phys == page => {}
+            Some(_) => return Err(MapError::AlreadyMapped),
+            None => map(phys_to_virt(page), page, flags)?,
+        }
+    }
+    Ok(())
+}
+
+/// Make the `size` bytes of physical memory at `phys` accessible, returns their virtual
+/// address. Memory that isn't in the direct map, like MMIO registers, gets new virtual pages.
+pub unsafe fn map_physical(phys: usize, size: usize, flags: PageFlags) -> Result<usize, MapError> {
+    if size <= DIRECT_MAP_SIZE && phys <= DIRECT_MAP_SIZE - size {
+        map_direct(phys, phys + size, flags)?;
+        return Ok(phys_to_virt(phys));
+    }
+
+    let first = phys & !(PAGE_SIZE - 1);
+    let pages = (phys + size - first).div_ceil(PAGE_SIZE);
+    let virt = interrupts::without_interrupts(|| {
+        let next_mmio = core::ptr::addr_of_mut!(NEXT_MMIO);
+        let virt = *next_mmio;
+        if MMIO_END - virt < pages * PAGE_SIZE {
+            return Err(MapError::OutOfMemory);
+        }
+        *next_mmio += pages * PAGE_SIZE;
+        Ok(virt)
+    })?;
+    for i in 0..pages {
+        map(virt + i * PAGE_SIZE, first + i * PAGE_SIZE, flags)?;
+    }
+    Ok(virt + (phys - first))
+}
+
+/// Map the kernel and every frame of RAM in the direct map, leaving the lower half empty, then
+/// switch from the page directory boot.S set up. The frame allocator must be initialized.
+pub fn init() {
+    unsafe {
+        let kernel_start = virt_to_phys((&crate::KERNEL_START as *const u32) as usize);
+        let kernel_end = virt_to_phys((&crate::KERNEL_END as *const u32) as usize);
+        let ram_end = (frame::last_usable().number() + 1) * PAGE_SIZE;
+
+        // Until the switch only what boot.S mapped is accessible. The page tables needed here
+        // are the first frames the frame allocator hands out, which are in low memory.
+        map_direct(0, ram_end, PageFlags::WRITABLE)
+            .and_then(|()| map_direct(kernel_start, kernel_end, PageFlags::WRITABLE))
+            .expect("The kernel and the RAM can be mapped");
+
+        asm!(r#"
+            .att_syntax
+            mov %eax, %cr3
+            mov %cr0, %eax
+            or %ecx, %eax
+            mov %eax, %cr0
+            "#,
+            inout("eax") virt_to_phys(core::ptr::addr_of!(PAGE_DIRECTORY) as usize) => _,
+            in("ecx") CR0_WRITE_PROTECT);
+    }
+}
@@ -0,0 +1,339 @@
+use core::fmt;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::frame::{self, Frame, FRAME_SIZE};
+use super::{phys_to_virt, virt_to_phys};
+use crate::sync::IrqSafeMutex;
+
+// https://en.wikipedia.org/wiki/Slab_allocation
+// Objects that are allocated and freed all the time, like packets and connections, each get a
+// cache of slabs. A slab is one frame cut in objects of the same size, so they don't fragment
+// the heap and taking or giving one back is only a few pointer writes.
+
+// Caches that `stats` can list
+const MAX_CACHES: usize = 32;
+
+// Every slab starts with this, followed by its objects
+#[repr(C)]
+struct Slab {
+    // The other slabs of the cache that have free objects. Full slabs are in no list, they are
+    // found again from the address of their objects.
+    previous: *mut Slab,
+    next: *mut Slab,
+    free: *mut FreeObject,
+    in_use: usize,
+}
+
+// Free objects are chained through their first bytes
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// The slabs of one size of objects.
+struct RawCache {
+    object_size: usize,
+    // Offset of the first object in a slab
+    first_object: usize,
+    objects_per_slab: usize,
+    partial: *mut Slab,
+    slabs: usize,
+    in_use: usize,
+    allocations: usize,
+}
+
+// The slabs are only reached through the lock of their cache
+unsafe impl Send for RawCache {}
+
+impl RawCache {
+    const fn new(size: usize, align: usize) -> Self {
+        let align = max(align, align_of::<FreeObject>());
+        let object_size = align_up(max(size, size_of::<FreeObject>()), align);
+        let first_object = align_up(size_of::<Slab>(), align);
+        assert!(
+            first_object + object_size <= FRAME_SIZE,
+            "Objects too large for a slab"
+        );
+        Self {
+            object_size,
+            first_object,
+            objects_per_slab: (FRAME_SIZE - first_object) / object_size,
+            partial: core::ptr::null_mut(),
+            slabs: 0,
+            in_use: 0,
+            allocations: 0,
+        }
+    }
+
+    unsafe fn push_partial(&mut self, slab: *mut Slab) {
+        (*slab).previous = core::ptr::null_mut();
+        (*slab).next = self.partial;
+        if !self.partial.is_null() {
+            (*self.partial).previous = slab;
+        }
+        self.partial = slab;
+    }
+
+    unsafe fn remove_partial(&mut self, slab: *mut Slab) {
+        let (previous, next) = ((*slab).previous, (*slab).next);
+        if previous.is_null() {
+            self.partial = next;
+        } else {
+            (*previous).next = next;
+        }
+        if !next.is_null() {
+            (*next).previous = previous;
+        }
+    }
+
+    /// Take a frame for a new slab, returns false if there is no physical memory left.
+    unsafe fn grow(&mut self) -> bool {
+        let Some(frame) = frame::allocate() else {
+            return false;
+        };
+        let slab = phys_to_virt(frame.start_address()) as *mut Slab;
+        // Chain the objects in order, so they are handed out in order
+        let mut free = core::ptr::null_mut();
+        for i in (0..self.objects_per_slab).rev() {
+            let object = slab
+                .cast::<u8>()
+                .add(self.first_object + i * self.object_size)
+                .cast::<FreeObject>();
+            object.write(FreeObject { next: free });
+            free = object;
+        }
+        slab.write(Slab {
+            previous: core::ptr::null_mut(),
+            next: core::ptr::null_mut(),
+            free,
+            in_use: 0,
+        });
+        self.push_partial(slab);
+        self.slabs += 1;
+        true
+    }
+
+    /// A free object, or null if there is no memory for a new slab.
+    unsafe fn alloc(&mut self) -> *mut u8 {
+        if self.partial.is_null() && !self.grow() {
+            return core::ptr::null_mut();
+        }
+        let slab = self.partial;
+        let object = (*slab).free;
+        (*slab).free = (*object).next;
+        (*slab).in_use += 1;
+        if (*slab).free.is_null() {
+            self.remove_partial(slab);
+        }
+        self.in_use += 1;
+        self.allocations += 1;
+        object.cast()
+    }
+
+    /// Give back an object taken with `alloc`.
+    unsafe fn free(&mut self, object: *mut u8) {
+        // Slabs are frames, so the slab of an object is the frame it is in
+        let slab = (object as usize & !(FRAME_SIZE - 1)) as *mut Slab;
+        if (*slab).free.is_null() {
+            self.push_partial(slab);
+        }
+        let object = object.cast::<FreeObject>();
+        object.write(FreeObject { next: (*slab).free });
+        (*slab).free = object;
+        (*slab).in_use -= 1;
+        self.in_use -= 1;
+
+        // Keep the last slab with free objects, so that allocating and freeing a single object
+        // over and over doesn't take and give back a frame each time
+        let only_partial = (*slab).previous.is_null() && (*slab).next.is_null();
+        if (*slab).in_use == 0 && !only_partial {
+            self.remove_partial(slab);
+            frame::deallocate(Frame::containing(virt_to_phys(slab as usize)));
+            self.slabs -= 1;
+        }
+    }
+}
+
+/// A cache of objects of type `T`, meant to be a static. It shows up in `stats` once used.
+pub struct ObjectCache<T> {
+    name: &'static str,
+    // Builds the objects given out by `alloc`
+    constructor: fn() -> T,
+    cache: IrqSafeMutex<RawCache>,
+    registered: AtomicBool,
+}
+
+impl<T: 'static> ObjectCache<T> {
+    pub const fn new(name: &'static str, constructor: fn() -> T) -> Self {
+        Self {
+            name,
+            constructor,
+            cache: IrqSafeMutex::new(RawCache::new(size_of::<T>(), align_of::<T>())),
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    /// A new object made by the constructor of the cache, or None if there is no memory left.
+    pub fn alloc(&'static self) -> Option<SlabBox<T>> {
+        let object = self.allocate()?;
+        unsafe { object.write((self.constructor)()) }
+        Some(SlabBox {
+            object,
+            cache: self,
+        })
+    }
+
+    fn allocate(&'static self) -> Option<*mut T> {
+        let object = unsafe { self.cache.lock().alloc() };
+        if !self.registered.swap(true, Ordering::AcqRel) {
+            register(self);
+        }
+        (!object.is_null()).then_some(object.cast())
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let cache = self.cache.lock();
+        SlabStats {
+            name: self.name,
+            object_size: cache.object_size,
+            objects_per_slab: cache.objects_per_slab,
+            slabs: cache.slabs,
+            objects_in_use: cache.in_use,
+            allocations: cache.allocations,
+        }
+    }
+}
+
+/// An object of an `ObjectCache`, given back to it when dropped.
+pub struct SlabBox<T: 'static> {
+    object: *mut T,
+    cache: &'static ObjectCache<T>,
+}
+
+// A `SlabBox` owns its object like a `Box` does
+unsafe impl<T: Send> Send for SlabBox<T> {}
+unsafe impl<T: Sync> Sync for SlabBox<T> {}
+
+impl<T> Deref for SlabBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.object }
+    }
+}
+
+impl<T> DerefMut for SlabBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.object }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for SlabBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            core::ptr::drop_in_place(self.object);
+            self.cache.cache.lock().free(self.object.cast());
+        }
+    }
+}
+
+/// What an `ObjectCache` holds at some point.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub name: &'static str,
+    /// Bytes taken by each object, padding included
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    /// Objects handed out since boot
+    pub allocations: usize,
+}
+
+impl fmt::Display for SlabStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}/{} objects of {} bytes in use, {} slabs, {} allocations",
+            self.name,
+            self.objects_in_use,
+            self.slabs * self.objects_per_slab,
+            self.object_size,
+            self.slabs,
+            self.allocations
+        )
+    }
+}
+
+// Lets the caches of every type be listed together
+trait Cache: Sync {
+    fn stats(&self) -> SlabStats;
+}
+
+impl<T: 'static> Cache for ObjectCache<T> {
+    fn stats(&self) -> SlabStats {
+        ObjectCache::stats(self)
+    }
+}
+
+static CACHES: IrqSafeMutex<[Option<&'static dyn Cache>; MAX_CACHES]> =
+    IrqSafeMutex::new([None; MAX_CACHES]);
+
+// Caches past `MAX_CACHES` work, they are only left out of `stats`
+fn register(cache: &'static dyn Cache) {
+    if let Some(slot) = CACHES.lock().iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(cache);
+    }
+}
+
+/// The statistics of every cache that was used.
+pub fn stats() -> impl Iterator<Item = SlabStats> {
+    let caches = *CACHES.lock();
+    caches.into_iter().flatten().map(|cache| cache.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    static OBJECTS: ObjectCache<[u32; 8]> = ObjectCache::new("test objects", || [7; 8]);
+
+    #[test_case]
+    fn objects_are_built_by_the_constructor() {
+        let objects: Vec<_> = (0..300).filter_map(|_| OBJECTS.alloc()).collect();
+        assert_eq!(objects.len(), 300);
+        assert!(objects.iter().all(|object| **object == [7; 8]));
+
+        let stats = OBJECTS.stats();
+        assert_eq!(stats.objects_in_use, 300);
+        assert_eq!(stats.slabs, 300usize.div_ceil(stats.objects_per_slab));
+        assert!(super::stats().any(|cache| cache.name == "test objects"));
+
+        // Empty slabs go back to the frame allocator, but the last one
+        drop(objects);
+        let stats = OBJECTS.stats();
+        assert_eq!(stats.objects_in_use, 0);
+        assert_eq!(stats.slabs, 1);
+    }
+}
@@ -0,0 +1,333 @@
+use core::ffi::CStr;
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+
+use crate::boot::{
+    self, ElfSections, Framebuffer, FramebufferKind, MultibootMemoryMappedType,
+    MULTIBOOT_FRAMEBUFFER_TYPE_EGA_TEXT, MULTIBOOT_FRAMEBUFFER_TYPE_INDEXED,
+    MULTIBOOT_FRAMEBUFFER_TYPE_RGB,
+};
+use crate::memory::virt_to_phys;
+
+// https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Boot-information-format
+pub const MULTIBOOT2_BOOTLOADER_MAGIC: u32 = 0x36D7_6289;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOT_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_MMAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ELF_SECTIONS: u32 = 9;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+const TAG_EFI_MMAP: u32 = 17;
+
+// Every tag starts on a multiple of this
+const TAG_ALIGN: usize = 8;
+
+// The only EFI memory type that is free once the bootloader is done
+const EFI_CONVENTIONAL_MEMORY: u32 = 7;
+const EFI_PAGE_SIZE: u64 = 4096;
+
+/// The fixed part of the Multiboot2 information, the tags follow it.
+#[allow(unused)]
+#[repr(C)]
+pub struct Multiboot2Info {
+    total_size: u32,
+    reserved: u32,
+}
+
+#[repr(C)]
+struct Tag {
+    r#type: u32,
+    size: u32,
+}
+
+impl Tag {
+    /// What follows the type and size of the tag.
+    fn data(&'static self) -> &'static [u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                (self as *const Tag).add(1) as *const u8,
+                self.size as usize - size_of::<Tag>(),
+            )
+        }
+    }
+
+    /// The `u32` at `offset` in the data of the tag.
+    fn u32_at(&'static self, offset: usize) -> Option<u32> {
+        let bytes = self.data().get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&'static self) -> Option<&'static str> {
+        CStr::from_bytes_until_nul(self.data()).ok()?.to_str().ok()
+    }
+}
+
+#[derive(Clone)]
+struct Tags {
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for Tags {
+    type Item = &'static Tag;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next + size_of::<Tag>() > self.end {
+            return None;
+        }
+        let tag = unsafe { &*(self.next as *const Tag) };
+        if tag.r#type == TAG_END || (tag.size as usize) < size_of::<Tag>() {
+            return None;
+        }
+        self.next = (self.next + tag.size as usize).next_multiple_of(TAG_ALIGN);
+        Some(tag)
+    }
+}
+
+impl Multiboot2Info {
+    fn tags(&self) -> Tags {
+        let start = self as *const Multiboot2Info as usize;
+        Tags {
+            next: start + size_of::<Multiboot2Info>(),
+            end: start + self.total_size as usize,
+        }
+    }
+
+    fn tag(&self, r#type: u32) -> Option<&'static Tag> {
+        self.tags().find(|tag| tag.r#type == r#type)
+    }
+
+    /// The command line given to the kernel in the bootloader configuration.
+    pub fn command_line(&self) -> Option<&'static str> {
+        self.tag(TAG_CMDLINE)?.string()
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&'static str> {
+        self.tag(TAG_BOOT_LOADER_NAME)?.string()
+    }
+
+    /// The files the bootloader loaded along with the kernel.
+    pub fn modules(&self) -> impl Iterator<Item = Multiboot2Module> + Clone {
+        self.tags()
+            .filter(|tag| tag.r#type == TAG_MODULE)
+            .filter_map(|tag| {
+                Some(Multiboot2Module {
+                    start: tag.u32_at(0)?,
+                    end: tag.u32_at(4)?,
+                })
+            })
+    }
+
+    /// The section headers of the kernel ELF file.
+    pub fn elf_sections(&self) -> Option<ElfSections> {
+        let tag = self.tag(TAG_ELF_SECTIONS)?;
+        let (count, entry_size, string_table) = (tag.u32_at(0)?, tag.u32_at(4)?, tag.u32_at(8)?);
+        // The headers are in the tag itself
+        let headers = tag.data().get(12..)?;
+        if headers.len() < count as usize * entry_size as usize {
+            return None;
+        }
+        unsafe { ElfSections::from_raw(headers.as_ptr() as usize, count, entry_size, string_table) }
+    }
+
+    /// The memory map given by the BIOS.
+    pub fn memory_map(&self) -> Option<MemoryMap> {
+        let tag = self.tag(TAG_MMAP)?;
+        let entry_size = tag.u32_at(0)? as usize;
+        // The entries follow their size and version
+        let entries = tag.data().get(8..)?;
+        if entry_size < size_of::<Multiboot2MMapEntry>() {
+            return None;
+        }
+        Some(MemoryMap {
+            next: entries.as_ptr() as usize,
+            end: entries.as_ptr() as usize + entries.len(),
+            entry_size,
+        })
+    }
+
+    /// The memory map given by the UEFI firmware, when booted from it.
+    pub fn efi_memory_map(&self) -> Option<EfiMemoryMap> {
+        let tag = self.tag(TAG_EFI_MMAP)?;
+        let descriptor_size = tag.u32_at(0)? as usize;
+        // The descriptors follow their size and version
+        let descriptors = tag.data().get(8..)?;
+        if descriptor_size < size_of::<EfiMemoryDescriptor>() {
+            return None;
+        }
+        Some(EfiMemoryMap {
+            next: descriptors.as_ptr() as usize,
+            end: descriptors.as_ptr() as usize + descriptors.len(),
+            descriptor_size,
+        })
+    }
+
+    /// The video mode the bootloader set up.
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let tag = self.tag(TAG_FRAMEBUFFER)?;
+        let data = tag.data();
+        if data.len() < 24 {
+            return None;
+        }
+        let addr = tag.u32_at(0)? as u64 | (tag.u32_at(4)? as u64) << 32;
+        let c = &data[24..];
+        let kind = match data[21] {
+            MULTIBOOT_FRAMEBUFFER_TYPE_INDEXED => FramebufferKind::Indexed {
+                // The palette is in the tag itself, after the number of colors
+                palette_addr: virt_to_phys(c.get(2..)?.as_ptr() as usize) as u32,
+                palette_colors: u16::from_le_bytes([*c.first()?, *c.get(1)?]),
+            },
+            MULTIBOOT_FRAMEBUFFER_TYPE_RGB => {
+                let c = c.get(..6)?;
+                FramebufferKind::Rgb {
+                    red_position: c[0],
+                    red_size: c[1],
+                    green_position: c[2],
+                    green_size: c[3],
+                    blue_position: c[4],
+                    blue_size: c[5],
+                }
+            }
+            MULTIBOOT_FRAMEBUFFER_TYPE_EGA_TEXT => FramebufferKind::EgaText,
+            _ => return None,
+        };
+        Some(Framebuffer {
+            addr,
+            pitch: tag.u32_at(8)?,
+            width: tag.u32_at(12)?,
+            height: tag.u32_at(16)?,
+            bpp: data[20],
+            kind,
+        })
+    }
+
+    /// A copy of the ACPI RSDP, the version 2 one if the firmware has it.
+    pub fn rsdp(&self) -> Option<&'static [u8]> {
+        self.tag(TAG_ACPI_NEW)
+            .or_else(|| self.tag(TAG_ACPI_OLD))
+            .map(Tag::data)
+    }
+
+    /// Ranges of physical memory that are in use before memory is managed: the first page, the
+    /// kernel image, the information structure with all its tags, the modules and the ELF
+    /// sections the bootloader loaded outside the kernel image.
+    fn reserved_ranges(&self) -> impl Iterator<Item = (u64, u64)> + Clone + '_ {
+        let info = virt_to_phys(self as *const Multiboot2Info as usize) as u64;
+        let sections = self
+            .elf_sections()
+            .map(|sections| sections.iter().as_slice())
+            .unwrap_or(&[]);
+        boot::always_reserved()
+            .into_iter()
+            .chain([(info, info + self.total_size as u64)])
+            .chain(
+                self.modules()
+                    .map(|module| (module.start as u64, module.end as u64)),
+            )
+            .chain(
+                sections
+                    .iter()
+                    .filter_map(|section| section.physical_range()),
+            )
+    }
+
+    /// Call `f` with the start and end of every piece of available memory below 4 GiB that
+    /// nothing uses yet, in ascending order inside each memory map entry. The BIOS memory map is
+    /// used if there is one, the UEFI one otherwise.
+    pub fn loop_through_usable_memory(&self, mut f: impl FnMut(u64, u64)) {
+        if let Some(memory_map) = self.memory_map() {
+            for mmap in memory_map {
+                if mmap.memory_type() == MultibootMemoryMappedType::Available {
+                    let end = mmap.addr + mmap.len;
+                    boot::loop_through_unreserved(mmap.addr, end, self.reserved_ranges(), &mut f);
+                }
+            }
+        } else if let Some(memory_map) = self.efi_memory_map() {
+            for descriptor in memory_map {
+                if descriptor.r#type == EFI_CONVENTIONAL_MEMORY {
+                    let start = descriptor.physical_start;
+                    let end = start + descriptor.page_count * EFI_PAGE_SIZE;
+                    boot::loop_through_unreserved(start, end, self.reserved_ranges(), &mut f);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Multiboot2Module {
+    /// Physical address of the start of the module.
+    pub start: u32,
+    /// Physical address of the end of the module.
+    pub end: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Multiboot2MMapEntry {
+    pub addr: u64,
+    pub len: u64,
+    pub r#type: u32,
+    reserved: u32,
+}
+
+impl Multiboot2MMapEntry {
+    pub fn memory_type(&self) -> MultibootMemoryMappedType {
+        MultibootMemoryMappedType::from_u32(self.r#type)
+    }
+}
+
+pub struct MemoryMap {
+    next: usize,
+    end: usize,
+    entry_size: usize,
+}
+
+impl Iterator for MemoryMap {
+    type Item = Multiboot2MMapEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next + self.entry_size > self.end {
+            return None;
+        }
+        let entry = unsafe { read_unaligned(self.next as *const Multiboot2MMapEntry) };
+        self.next += self.entry_size;
+        Some(entry)
+    }
+}
+
+// https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-getmemorymap
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct EfiMemoryDescriptor {
+    pub r#type: u32,
+    padding: u32,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub page_count: u64,
+    pub attribute: u64,
+}
+
+pub struct EfiMemoryMap {
+    next: usize,
+    end: usize,
+    descriptor_size: usize,
+}
+
+impl Iterator for EfiMemoryMap {
+    type Item = EfiMemoryDescriptor;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next + self.descriptor_size > self.end {
+            return None;
+        }
+        let descriptor = unsafe { read_unaligned(self.next as *const EfiMemoryDescriptor) };
+        // The firmware may use descriptors larger than the ones in the specification
+        self.next += self.descriptor_size;
+        Some(descriptor)
+    }
+}
@@ -0,0 +1,158 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::interrupts;
+
+/// A lock that busy-waits until it is free.
+///
+/// An interrupt handler must not take a lock the code it interrupts may hold, it would wait
+/// forever: data that interrupt handlers use goes in an `IrqSafeMutex`.
+pub struct Spinlock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+// The lock gives access to the data to a single holder at a time
+unsafe impl<T: Send> Sync for Spinlock<T> {}
+unsafe impl<T: Send> Send for Spinlock<T> {}
+
+#[allow(dead_code)]
+impl<T> Spinlock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> SpinlockGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            // Only try again once it looks free, without writing to it meanwhile
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinlockGuard<'_, T>> {
+        self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinlockGuard { lock: self })
+    }
+
+    /// The data, whether the lock is held or not. Only for when the holder will never run again,
+    /// like while the kernel panics.
+    pub unsafe fn force_get(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+/// Access to the data of a `Spinlock`, which is released when the guard is dropped.
+pub struct SpinlockGuard<'a, T> {
+    lock: &'a Spinlock<T>,
+}
+
+impl<T> Deref for SpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A spinlock that keeps interrupts disabled while it is held, so both interrupt handlers and
+/// the code they interrupt can take it.
+pub struct IrqSafeMutex<T> {
+    inner: Spinlock<T>,
+}
+
+#[allow(dead_code)]
+impl<T> IrqSafeMutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: Spinlock::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqSafeMutexGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_were_enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<'_, T>> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqSafeMutexGuard {
+                guard: ManuallyDrop::new(guard),
+                interrupts_were_enabled,
+            }),
+            None => {
+                if interrupts_were_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    /// The data, whether the lock is held or not. Only for when the holder will never run again,
+    /// like while the kernel panics.
+    pub unsafe fn force_get(&self) -> *mut T {
+        self.inner.force_get()
+    }
+}
+
+/// Access to the data of an `IrqSafeMutex`. Dropping it releases the lock, then enables
+/// interrupts again if they were enabled when it was taken.
+pub struct IrqSafeMutexGuard<'a, T> {
+    guard: ManuallyDrop<SpinlockGuard<'a, T>>,
+    interrupts_were_enabled: bool,
+}
+
+impl<T> Deref for IrqSafeMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqSafeMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqSafeMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // An interrupt must not come while the lock is still held
+        unsafe { ManuallyDrop::drop(&mut self.guard) }
+        if self.interrupts_were_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::io::outl;
+
+// Writing to the isa-debug-exit device of QEMU makes it exit with (value << 1) | 1, see run.sh
+const EXIT_PORT: u16 = 0xf4;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+pub fn exit_qemu(code: ExitCode) -> ! {
+    unsafe { outl(EXIT_PORT, code as u32) };
+    // Not running in QEMU, nothing else to do
+    loop {
+        crate::interrupts::wait_for_interrupt();
+    }
+}
+
+/// A `#[test_case]`, printed with its path as it runs.
+pub trait Test {
+    fn run(&self);
+}
+
+impl<T: Fn()> Test for T {
+    fn run(&self) {
+        print!("{}... ", core::any::type_name::<T>());
+        self();
+        println!("ok");
+    }
+}
+
+/// Run every test, then exit QEMU. A failing test panics, and the panic handler exits QEMU with
+/// `ExitCode::Failed`.
+pub fn runner(tests: &[&dyn Test]) {
+    println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(ExitCode::Success);
+}
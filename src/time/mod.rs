@@ -0,0 +1,123 @@
+use core::ops::Add;
+use core::time::Duration;
+
+use crate::interrupts::{self, irq};
+
+pub mod pit;
+pub mod rtc;
+
+pub use rtc::DateTime;
+
+/// How many times per second the system timer ticks
+pub const TICK_FREQUENCY: u32 = 1000;
+
+// Only written by the timer IRQ handler, read with interrupts disabled since a u64 can't be
+// read atomically on i386.
+static mut TICKS: u64 = 0;
+static mut NANOS_PER_TICK: u64 = 0;
+
+// Wall clock time when the timer started, the CMOS clock is only read once.
+static mut BOOT_TIMESTAMP: u64 = 0;
+
+pub fn init() {
+    let divisor = pit::start_periodic(TICK_FREQUENCY);
+    unsafe {
+        *core::ptr::addr_of_mut!(NANOS_PER_TICK) =
+            divisor as u64 * 1_000_000_000 / pit::BASE_FREQUENCY as u64;
+    }
+    let boot_timestamp = rtc::read().and_then(|date| date.unix_timestamp());
+    if boot_timestamp.is_none() {
+        log!(
+            Warn,
+            "The CMOS clock holds no valid date, counting from 1970."
+        );
+    }
+    unsafe { *core::ptr::addr_of_mut!(BOOT_TIMESTAMP) = boot_timestamp.unwrap_or(0) }
+    irq::register_handler(pit::IRQ, tick).expect("Nobody else uses the PIT IRQ");
+}
+
+fn tick() {
+    unsafe { *core::ptr::addr_of_mut!(TICKS) += 1 }
+}
+
+/// Number of timer ticks since `init`.
+pub fn ticks() -> u64 {
+    interrupts::without_interrupts(|| unsafe { *core::ptr::addr_of!(TICKS) })
+}
+
+/// Time elapsed since the system timer was started. It never goes backward.
+pub fn uptime() -> Duration {
+    let nanos_per_tick = unsafe { *core::ptr::addr_of!(NANOS_PER_TICK) };
+    Duration::from_nanos(ticks() * nanos_per_tick)
+}
+
+/// Current UTC wall clock time.
+pub fn now() -> DateTime {
+    let boot_timestamp = unsafe { *core::ptr::addr_of!(BOOT_TIMESTAMP) };
+    DateTime::from_unix_timestamp(boot_timestamp + uptime().as_secs())
+}
+
+/// A point on the monotonic clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(Duration);
+
+#[allow(dead_code)]
+impl Instant {
+    pub fn now() -> Self {
+        Self(uptime())
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        uptime().saturating_sub(self.0)
+    }
+
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Instant(self.0 + rhs)
+    }
+}
+
+/// A point in time by which something should have happened, for timeouts and retransmissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(Instant);
+
+#[allow(dead_code)]
+impl Deadline {
+    pub fn after(duration: Duration) -> Self {
+        Self(Instant::now() + duration)
+    }
+
+    pub fn has_passed(&self) -> bool {
+        Instant::now() >= self.0
+    }
+
+    /// Time left before the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    /// Push the deadline back by `duration`, e.g. to back off a retransmission timer.
+    pub fn extend(&mut self, duration: Duration) {
+        self.0 = self.0 + duration;
+    }
+}
+
+/// Halt until the deadline has passed. Interrupts must be enabled, or we never wake up.
+#[allow(dead_code)]
+pub fn sleep_until(deadline: Deadline) {
+    while !deadline.has_passed() {
+        interrupts::wait_for_interrupt();
+    }
+}
+
+#[allow(dead_code)]
+pub fn sleep(duration: Duration) {
+    sleep_until(Deadline::after(duration));
+}
@@ -0,0 +1,24 @@
+use crate::io::outb;
+
+// https://wiki.osdev.org/Programmable_Interval_Timer
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+pub const IRQ: u8 = 0;
+
+const CHANNEL0_DATA: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+// Channel 0, lobyte/hibyte access, mode 2 (rate generator), binary counting
+const CHANNEL0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+/// Make channel 0 fire IRQ 0 periodically at about `frequency` Hz. Returns the divisor that was
+/// programmed, the actual frequency being `BASE_FREQUENCY / divisor`.
+pub fn start_periodic(frequency: u32) -> u32 {
+    // A divisor of 0 means 65536 to the PIT
+    let divisor = (BASE_FREQUENCY / frequency).clamp(1, 65536);
+    unsafe {
+        outb(COMMAND, CHANNEL0_RATE_GENERATOR);
+        outb(CHANNEL0_DATA, (divisor & 0xFF) as u8);
+        outb(CHANNEL0_DATA, (divisor >> 8 & 0xFF) as u8);
+    }
+    divisor
+}
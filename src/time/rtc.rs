@@ -0,0 +1,271 @@
+use core::fmt;
+
+use crate::io::{inb, outb};
+
+// https://wiki.osdev.org/CMOS
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+// Not standard, but where the FADT points on every PC we care about
+const REG_CENTURY: u8 = 0x32;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOUR_PM: u8 = 1 << 7;
+
+fn read_register(register: u8) -> u8 {
+    unsafe {
+        outb(CMOS_ADDRESS, register);
+        inb(CMOS_DATA)
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+fn read_raw() -> RawTime {
+    while update_in_progress() {}
+    RawTime {
+        second: read_register(REG_SECONDS),
+        minute: read_register(REG_MINUTES),
+        hour: read_register(REG_HOURS),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+        century: read_register(REG_CENTURY),
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// Read the CMOS clock, which we expect to be kept in UTC. None if it doesn't hold a valid date,
+/// like when its battery is dead.
+pub fn read() -> Option<DateTime> {
+    // The clock may tick while we read its registers, read until we get the same value twice.
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status_b = read_register(REG_STATUS_B);
+    let pm = raw.hour & HOUR_PM != 0;
+    raw.hour &= !HOUR_PM;
+
+    if status_b & STATUS_B_BINARY == 0 {
+        raw.second = from_bcd(raw.second);
+        raw.minute = from_bcd(raw.minute);
+        raw.hour = from_bcd(raw.hour);
+        raw.day = from_bcd(raw.day);
+        raw.month = from_bcd(raw.month);
+        raw.year = from_bcd(raw.year);
+        raw.century = from_bcd(raw.century);
+    }
+
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight and 12 PM is noon
+        raw.hour %= 12;
+        if pm {
+            raw.hour += 12;
+        }
+    }
+
+    let century = if raw.century != 0 { raw.century } else { 20 };
+
+    let date = DateTime {
+        year: century as u16 * 100 + raw.year as u16,
+        month: raw.month,
+        day: raw.day,
+        hour: raw.hour,
+        minute: raw.minute,
+        second: raw.second,
+    };
+    date.is_valid().then_some(date)
+}
+
+const DAY_NAMES: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A UTC calendar date and time, with a one second resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+// Days between 1970-01-01 and the given date, http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl DateTime {
+    pub fn from_unix_timestamp(timestamp: u64) -> Self {
+        let days = (timestamp / 86400) as i64;
+        let seconds_of_day = timestamp % 86400;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01T00:00:00Z, None for earlier dates.
+    pub fn unix_timestamp(&self) -> Option<u64> {
+        let days = u64::try_from(days_from_civil(self.year as i64, self.month, self.day)).ok()?;
+        Some(days * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64)
+    }
+
+    // Every field in its range, though not that the day exists in the month
+    fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// 0 for Sunday up to 6 for Saturday
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        ((days_from_civil(self.year as i64, self.month, self.day) + 4).rem_euclid(7)) as u8
+    }
+
+    /// Formats as an RFC 7231 IMF-fixdate, for the `Date` and `Last-Modified` HTTP headers.
+    pub fn http_date(&self) -> HttpDate {
+        HttpDate(*self)
+    }
+}
+
+/// ISO 8601, for logs: `1994-11-06T08:49:37Z`
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// `Sun, 06 Nov 1994 08:49:37 GMT`
+pub struct HttpDate(DateTime);
+
+impl fmt::Display for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = &self.0;
+        write!(
+            f,
+            "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            DAY_NAMES[date.weekday() as usize],
+            date.day,
+            MONTH_NAMES[(date.month as usize).clamp(1, 12) - 1],
+            date.year,
+            date.hour,
+            date.minute,
+            date.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn unix_timestamp_round_trips() {
+        let date = DateTime {
+            year: 1994,
+            month: 11,
+            day: 6,
+            hour: 8,
+            minute: 49,
+            second: 37,
+        };
+        assert_eq!(date.unix_timestamp(), Some(784_111_777));
+        assert_eq!(DateTime::from_unix_timestamp(784_111_777), date);
+    }
+
+    #[test_case]
+    fn no_unix_timestamp_before_1970() {
+        let date = DateTime {
+            year: 1969,
+            month: 12,
+            day: 31,
+            hour: 23,
+            minute: 59,
+            second: 59,
+        };
+        assert!(date.is_valid());
+        assert_eq!(date.unix_timestamp(), None);
+    }
+
+    #[test_case]
+    fn fields_out_of_range_are_invalid() {
+        let date = DateTime::from_unix_timestamp(0);
+        assert!(date.is_valid());
+        assert!(!DateTime { month: 13, ..date }.is_valid());
+        assert!(!DateTime { day: 0, ..date }.is_valid());
+        assert!(!DateTime { hour: 24, ..date }.is_valid());
+        assert!(!DateTime { second: 60, ..date }.is_valid());
+    }
+}